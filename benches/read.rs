@@ -0,0 +1,48 @@
+//! Cost of handing a message to many readers, with the payload shared
+//! between them as it is now and copied out for each reader as it used to be.
+//!
+//! Run with `cargo bench`.
+
+use spmc_logger::{Logger, NoIntegrity};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const MESSAGES: usize = 200;
+
+// Writes `MESSAGES` messages of `size` bytes and has each of `readers` readers
+// read every one, copying the payload out if `copy` is set. Returns the time
+// spent reading.
+fn run(size: usize, readers: usize, copy: bool) -> Duration {
+    let logger = Logger::builder(readers, 1).integrity(NoIntegrity).build().unwrap();
+    let mut w = logger.writer().unwrap();
+    let mut rs: Vec<_> = (0..readers).map(|_| logger.subscribe().unwrap()).collect();
+    let payload = vec![7u8; size];
+
+    let mut reading = Duration::ZERO;
+    for _ in 0..MESSAGES {
+        w.write(&payload).unwrap();
+        let start = Instant::now();
+        for r in &mut rs {
+            let res = r.read().unwrap().unwrap();
+            if copy {
+                black_box(res.message.to_vec());
+            } else {
+                black_box(res.message);
+            }
+        }
+        reading += start.elapsed();
+    }
+    reading
+}
+
+fn main() {
+    println!("{:>10} {:>8} {:>14} {:>14}", "size", "readers", "shared/read", "copied/read");
+    for size in [64, 64 << 10, 1 << 20] {
+        for readers in [1, 8, 32] {
+            let reads = (MESSAGES * readers) as u32;
+            let shared = run(size, readers, false) / reads;
+            let copied = run(size, readers, true) / reads;
+            println!("{:>10} {:>8} {:>14?} {:>14?}", size, readers, shared, copied);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::cipher::EncryptionKey;
+use crate::integrity::Integrity;
+use crate::key::{Algorithm, Key, Keys};
+use crate::merkle::{Audit, Tree};
+use crate::observer::{default_observer, Observer};
+use crate::storage::{Recovered, Storage, SEGMENT_BYTES};
+use crate::{Logger, LoggerError, Shared};
+use ring::hmac;
+use ring::signature::Ed25519KeyPair;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// What [`Writer::write`](crate::Writer::write) does when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Fail with [`LoggerError::Full`] and leave the buffer as it is.
+    #[default]
+    Reject,
+    /// Wait for the readers to free a slot. With a timeout, the write fails
+    /// with [`LoggerError::Full`] if nothing is freed in time.
+    Block { timeout: Option<Duration> },
+    /// Evict the oldest message, even if some readers have not read it yet.
+    /// Those readers skip ahead to the new oldest message.
+    OverwriteOldest,
+    /// Throw the new message away and count it in
+    /// [`Logger::dropped`](crate::Logger::dropped). The write fails with
+    /// [`LoggerError::Dropped`].
+    DropNewest,
+}
+
+/// Configures a [`Logger`] before creating it.
+///
+/// ```
+/// use spmc_logger::{Backpressure, Logger};
+///
+/// let logger = Logger::builder(3, 100)
+///     .backpressure(Backpressure::OverwriteOldest)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder<I = Keys> {
+    pub(crate) num_readers: usize,
+    pub(crate) size: usize,
+    pub(crate) max_bytes: Option<usize>,
+    pub(crate) backpressure: Backpressure,
+    pub(crate) chained: bool,
+    pub(crate) multi_producer: bool,
+    pub(crate) visibility_timeout: Option<Duration>,
+    audit: Option<(Arc<Ed25519KeyPair>, u64)>,
+    pub(crate) encryption: Option<EncryptionKey>,
+    pub(crate) observer: Arc<dyn Observer>,
+    pub(crate) integrity: I,
+}
+
+impl Builder {
+    pub(crate) fn new(num_readers: usize, size: usize) -> Self {
+        Builder {
+            num_readers,
+            size,
+            max_bytes: None,
+            backpressure: Backpressure::default(),
+            chained: false,
+            multi_producer: false,
+            visibility_timeout: None,
+            audit: None,
+            encryption: None,
+            observer: default_observer(),
+            integrity: Keys::new(),
+        }
+    }
+
+    /// Picks how messages are signed. HMAC-SHA256 is the default.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.integrity.set_algorithm(algorithm);
+        self
+    }
+
+    /// Signs messages with the HMAC `key` instead of a randomly generated
+    /// one, so another process holding the same key can check them.
+    pub fn key(mut self, key: hmac::Key) -> Self {
+        self.integrity.set_key(Some(Key::Hmac(key)), None, None);
+        self
+    }
+
+    /// Signs messages with Ed25519 using `key`, so they can be checked with
+    /// its public key alone.
+    pub fn signing_key(mut self, key: Ed25519KeyPair) -> Self {
+        self.integrity.set_key(Some(Key::Ed25519(Arc::new(key))), None, None);
+        self
+    }
+
+    /// Signs messages with a key made from `bytes`: the key itself for
+    /// HMAC, a PKCS#8 document for Ed25519.
+    pub fn key_bytes(mut self, bytes: &[u8]) -> Self {
+        self.integrity.set_key(None, Some(bytes), None);
+        self
+    }
+
+    /// Signs messages with the key saved at `path`. If there is no file yet,
+    /// a key is generated and saved there, readable only by its owner. Keys
+    /// rotated in later are saved next to it as `<path>.<id>`.
+    pub fn key_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.integrity.set_key(None, None, Some(path.as_ref().to_path_buf()));
+        self
+    }
+}
+
+impl<I: Integrity> Builder<I> {
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Also limits the buffer to `max_bytes`: the retained messages can't
+    /// take up more than that between them, on top of there being at most
+    /// `size` of them. Each message counts as its entry in the buffer plus
+    /// what it keeps on the heap: its payload as stored, its tag, and the tag
+    /// before it in a chained log, see [`Logger::message_bytes`]. The backpressure policy applies when
+    /// either limit is reached, and a write that would not fit even in an
+    /// empty buffer fails with [`LoggerError::TooLarge`].
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Chains the messages together: each tag also covers the offset and
+    /// the tag of the message before it, so a reader can tell when messages
+    /// were removed, reordered or replayed. See [`Response::chain_break`].
+    ///
+    /// [`Response::chain_break`]: crate::Response::chain_break
+    pub fn chained(mut self, chained: bool) -> Self {
+        self.chained = chained;
+        self
+    }
+
+    /// Lets [`Logger::writer`](crate::Logger::writer) hand out any number of
+    /// writers. Writes from all of them land in a single log, each batch at
+    /// consecutive offsets, in the order the writers got to them. They are
+    /// made one at a time, so a writer blocked on a full buffer by
+    /// [`Backpressure::Block`] stalls all the others until it is done.
+    pub fn multi_producer(mut self, multi_producer: bool) -> Self {
+        self.multi_producer = multi_producer;
+        self
+    }
+
+    /// Makes readers acknowledge every message they read with
+    /// [`Reader::ack`](crate::Reader::ack): a message only counts as read,
+    /// and can only be dropped, once it is acknowledged. A message that is
+    /// not acknowledged within `timeout` of being read is delivered again,
+    /// to the same slot of the quorum.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps a Merkle tree over every message written, so a third party can
+    /// be shown that a message is in the log without the HMAC key. Every
+    /// `interval` messages (never if 0) the tree head is signed with `signer`
+    /// into a [`Checkpoint`](crate::Checkpoint). A persistent logger saves the
+    /// tree next to the log.
+    ///
+    /// The tree covers every message ever written, evicted or not. An
+    /// in-memory logger keeps all of it, 64 bytes a message; a persistent one
+    /// only keeps about 64 bytes per 1024 messages and reads the rest back
+    /// from its leaf file when a proof needs it.
+    pub fn audit(mut self, signer: Ed25519KeyPair, interval: u64) -> Self {
+        self.audit = Some((Arc::new(signer), interval));
+        self
+    }
+
+    /// Encrypts every message with `key`, in memory and on disk. Readers get
+    /// the decrypted message, or [`LoggerError::Decryption`] if it does not
+    /// decrypt. Integrity tags and Merkle leaves cover the encrypted bytes.
+    pub fn encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    /// Reports what the logger does to `observer`. Pass an `Arc` to keep a
+    /// handle on it.
+    pub fn observer<O: Observer>(mut self, observer: O) -> Self {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    /// Protects messages with `integrity` instead of the default signing
+    /// [`Keys`](crate::Keys).
+    pub fn integrity<J: Integrity>(self, integrity: J) -> Builder<J> {
+        Builder {
+            num_readers: self.num_readers,
+            size: self.size,
+            max_bytes: self.max_bytes,
+            backpressure: self.backpressure,
+            chained: self.chained,
+            multi_producer: self.multi_producer,
+            visibility_timeout: self.visibility_timeout,
+            audit: self.audit,
+            encryption: self.encryption,
+            observer: self.observer,
+            integrity,
+        }
+    }
+
+    /// Creates an in-memory logger. Without a key or key file, a random key
+    /// is generated, which fails if the system's random number generator
+    /// does.
+    pub fn build(mut self) -> Result<Logger<I>, LoggerError> {
+        self.integrity.open(None)?;
+        let (config, integrity) = self.split();
+        config.finish(Arc::new(integrity), None, None)
+    }
+
+    /// Opens a persistent logger backed by the segment files in `dir`,
+    /// creating the directory if needed. Messages that had not been read by
+    /// the whole quorum when the logger was last used are read again from
+    /// the start by every reader.
+    pub fn open<P: AsRef<Path>>(mut self, dir: P) -> Result<Logger<I>, LoggerError> {
+        let dir = dir.as_ref();
+        let (storage, recovered) = self.open_storage(dir)?;
+        self.integrity.open(Some(dir))?;
+        let (config, integrity) = self.split();
+        config.finish(Arc::new(integrity), None, Some((dir, storage, recovered)))
+    }
+}
+
+impl<I> Builder<I> {
+    // Opens the segment files in `dir` and checks that what they hold still
+    // fits in the buffer and the quorum. Missing records take up a slot too,
+    // see `Logger::from_parts`, so it is the offsets that have to fit.
+    fn open_storage(&self, dir: &Path) -> Result<(Storage, Recovered), LoggerError> {
+        let (storage, recovered) = Storage::open(dir, SEGMENT_BYTES)?;
+        if recovered.tail - recovered.head > self.size as u64 {
+            return Err(LoggerError::Full { capacity: self.size, max_bytes: None });
+        }
+        if recovered.cursors.len() > self.num_readers {
+            return Err(LoggerError::ReaderRejected { num_readers: self.num_readers });
+        }
+        Ok((storage, recovered))
+    }
+
+    // Takes the integrity check out, leaving the rest of the configuration
+    // to be shared with topics.
+    fn split(self) -> (Builder<()>, I) {
+        let config = Builder {
+            num_readers: self.num_readers,
+            size: self.size,
+            max_bytes: self.max_bytes,
+            backpressure: self.backpressure,
+            chained: self.chained,
+            multi_producer: self.multi_producer,
+            visibility_timeout: self.visibility_timeout,
+            audit: self.audit,
+            encryption: self.encryption,
+            observer: self.observer,
+            integrity: (),
+        };
+        (config, self.integrity)
+    }
+}
+
+impl Builder<()> {
+    /// Creates the topic `name` of `num_readers` readers and `size` messages
+    /// that is otherwise configured like the logger it belongs to, persisted
+    /// in `dir` if that logger is. It encrypts with a key of its own, so its
+    /// offsets never share a nonce with another log's.
+    pub(crate) fn topic<I: Integrity>(
+        mut self,
+        name: &str,
+        num_readers: usize,
+        size: usize,
+        integrity: Arc<I>,
+        parent: Weak<Shared<I>>,
+        dir: Option<PathBuf>,
+    ) -> Result<Logger<I>, LoggerError> {
+        self.num_readers = num_readers;
+        self.size = size;
+        self.encryption = self.encryption.map(|key| key.for_topic(name));
+        let storage = match &dir {
+            Some(dir) => {
+                let (storage, recovered) = self.open_storage(dir)?;
+                Some((dir.as_path(), storage, recovered))
+            }
+            None => None,
+        };
+        self.finish(integrity, Some((parent, name.to_string())), storage)
+    }
+
+    fn finish<I: Integrity>(
+        self,
+        integrity: Arc<I>,
+        parent: Option<(Weak<Shared<I>>, String)>,
+        storage: Option<(&Path, Storage, Recovered)>,
+    ) -> Result<Logger<I>, LoggerError> {
+        let audit = match (self.audit.clone(), &storage) {
+            (Some((signer, interval)), Some((dir, _, recovered))) => {
+                Some(Audit::new(Tree::open(&dir.join("tree"), recovered)?, signer, interval))
+            }
+            (Some((signer, interval)), None) => Some(Audit::new(Tree::new(), signer, interval)),
+            (None, _) => None,
+        };
+        let storage = storage.map(|(_, storage, recovered)| (storage, recovered));
+        Ok(Logger::from_parts(self, integrity, parent, audit, storage))
+    }
+}
@@ -0,0 +1,118 @@
+use crate::LoggerError;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::hkdf::{self, HKDF_SHA256};
+
+/// Length of the keys both ciphers take.
+pub const ENCRYPTION_KEY_LEN: usize = 32;
+
+// the HKDF salt topic keys are derived with
+const TOPIC_SALT: &[u8] = b"spmc-logger topic key v1";
+
+/// The AEAD cipher messages are encrypted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+/// A key for encrypting messages at rest, see
+/// [`Builder::encryption`](crate::Builder::encryption).
+///
+/// Nonces are derived from the message offsets, which never repeat within a
+/// log, so a key must not be shared between logs. The topics of a logger
+/// each encrypt with a key derived from the logger's.
+#[derive(Debug, Clone)]
+pub struct EncryptionKey {
+    key: LessSafeKey,
+    // what the keys of topics are derived from
+    prk: hkdf::Prk,
+}
+
+impl EncryptionKey {
+    /// Makes a key for `cipher` out of `ENCRYPTION_KEY_LEN` bytes.
+    pub fn new(cipher: Cipher, bytes: &[u8]) -> Result<Self, LoggerError> {
+        let algorithm = match cipher {
+            Cipher::Aes256Gcm => &aead::AES_256_GCM,
+            Cipher::ChaCha20Poly1305 => &aead::CHACHA20_POLY1305,
+        };
+        let key = UnboundKey::new(algorithm, bytes).map_err(|_| LoggerError::InvalidKey)?;
+        let prk = hkdf::Salt::new(HKDF_SHA256, TOPIC_SALT).extract(bytes);
+        Ok(EncryptionKey { key: LessSafeKey::new(key), prk })
+    }
+
+    /// The key the topic `name` encrypts with, derived from this one with
+    /// HKDF over the name.
+    pub(crate) fn for_topic(&self, name: &str) -> EncryptionKey {
+        let info = [name.as_bytes()];
+        // HKDF only refuses to make keys of many kilobytes
+        let okm = self.prk.expand(&info, self.key.algorithm()).expect("key is too long to derive");
+        EncryptionKey { key: LessSafeKey::new(UnboundKey::from(okm)), prk: self.prk.clone() }
+    }
+
+    /// How much longer a message gets when it is encrypted.
+    pub(crate) fn overhead(&self) -> usize {
+        self.key.algorithm().tag_len()
+    }
+
+    /// Encrypts the message at `offset`, appending the authentication tag.
+    pub(crate) fn seal(&self, offset: u64, data: &[u8]) -> Vec<u8> {
+        let mut buf = data.to_vec();
+        self.key
+            .seal_in_place_append_tag(nonce(offset), Aad::empty(), &mut buf)
+            // ring only refuses messages of many gigabytes
+            .expect("message is too long to encrypt");
+        buf
+    }
+
+    /// Decrypts the message at `offset`, or returns `None` if it was not
+    /// encrypted with this key at this offset.
+    pub(crate) fn open(&self, offset: u64, sealed: &[u8]) -> Option<Vec<u8>> {
+        let mut buf = sealed.to_vec();
+        let len = self.key.open_in_place(nonce(offset), Aad::empty(), &mut buf).ok()?.len();
+        buf.truncate(len);
+        Some(buf)
+    }
+}
+
+// 96-bit nonce: four zero bytes followed by the offset.
+fn nonce(offset: u64) -> Nonce {
+    let mut bytes = [0u8; aead::NONCE_LEN];
+    bytes[4..].copy_from_slice(&offset.to_be_bytes());
+    Nonce::assume_unique_for_key(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_messages_only_open_at_their_offset() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let key = EncryptionKey::new(cipher, &[7; ENCRYPTION_KEY_LEN]).unwrap();
+            let sealed = key.seal(3, b"secret");
+            assert_ne!(&sealed[..6], b"secret");
+            assert_eq!(key.open(3, &sealed).unwrap(), b"secret");
+            assert!(key.open(4, &sealed).is_none());
+
+            let other = EncryptionKey::new(cipher, &[8; ENCRYPTION_KEY_LEN]).unwrap();
+            assert!(other.open(3, &sealed).is_none());
+        }
+        assert!(matches!(
+            EncryptionKey::new(Cipher::Aes256Gcm, &[0; 16]),
+            Err(LoggerError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn topics_get_keys_of_their_own() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let key = EncryptionKey::new(cipher, &[7; ENCRYPTION_KEY_LEN]).unwrap();
+            let (a, b) = (key.for_topic("a"), key.for_topic("b"));
+            let sealed = [key.seal(0, b"secret"), a.seal(0, b"secret"), b.seal(0, b"secret")];
+            assert!(sealed[0] != sealed[1] && sealed[0] != sealed[2] && sealed[1] != sealed[2]);
+            assert!(b.open(0, &sealed[1]).is_none());
+            // derived the same way every time, so a reopened topic can read its messages
+            assert_eq!(key.for_topic("a").open(0, &sealed[1]).unwrap(), b"secret");
+        }
+    }
+}
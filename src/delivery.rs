@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+/// Identifies a message handed out by a logger built with
+/// [`Builder::visibility_timeout`](crate::Builder::visibility_timeout). Give
+/// it back to [`Reader::ack`](crate::Reader::ack) once the message has been
+/// dealt with, or to [`Reader::nack`](crate::Reader::nack) to have it
+/// delivered again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeliveryToken {
+    // the logger or topic the message was read from, see `Shared::log`
+    pub(crate) log: u64,
+    pub(crate) slot: usize,
+    pub(crate) offset: u64,
+}
+
+impl DeliveryToken {
+    /// Offset of the message that was delivered.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+// The messages a reader slot was handed but has not acknowledged yet, and
+// when each of them is due to be delivered again.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    by_offset: BTreeMap<u64, Instant>,
+    by_due: BTreeSet<(Instant, u64)>,
+}
+
+impl InFlight {
+    pub(crate) fn insert(&mut self, offset: u64, due: Instant) {
+        if let Some(old) = self.by_offset.insert(offset, due) {
+            self.by_due.remove(&(old, offset));
+        }
+        self.by_due.insert((due, offset));
+    }
+
+    /// Returns when the message was due, or `None` if it was not in flight.
+    pub(crate) fn remove(&mut self, offset: u64) -> Option<Instant> {
+        let due = self.by_offset.remove(&offset)?;
+        self.by_due.remove(&(due, offset));
+        Some(due)
+    }
+
+    pub(crate) fn contains(&self, offset: u64) -> bool {
+        self.by_offset.contains_key(&offset)
+    }
+
+    /// The message that has been due the longest, if any is due by `now`.
+    pub(crate) fn due(&self, now: Instant) -> Option<u64> {
+        self.by_due.first().filter(|(due, _)| *due <= now).map(|(_, offset)| *offset)
+    }
+
+    /// When the next message is due.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.by_due.first().map(|(due, _)| *due)
+    }
+
+    /// The oldest message still in flight.
+    pub(crate) fn oldest(&self) -> Option<u64> {
+        self.by_offset.keys().next().copied()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.by_offset.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn messages_come_due_in_order() {
+        let now = Instant::now();
+        let mut in_flight = InFlight::default();
+        in_flight.insert(3, now + Duration::from_secs(1));
+        in_flight.insert(5, now);
+        in_flight.insert(4, now + Duration::from_secs(2));
+        assert_eq!((in_flight.oldest(), in_flight.due(now)), (Some(3), Some(5)));
+
+        // delivered again, so due later
+        in_flight.insert(5, now + Duration::from_secs(3));
+        assert_eq!(in_flight.due(now), None);
+        assert_eq!(in_flight.next_due(), Some(now + Duration::from_secs(1)));
+        assert_eq!(in_flight.due(now + Duration::from_secs(2)), Some(3));
+
+        assert!(in_flight.remove(3).is_some() && in_flight.remove(3).is_none());
+        assert!(!in_flight.contains(3) && in_flight.contains(4));
+        assert_eq!(in_flight.oldest(), Some(4));
+        in_flight.remove(4);
+        in_flight.remove(5);
+        assert!(in_flight.is_empty() && in_flight.next_due().is_none());
+    }
+}
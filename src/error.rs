@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong when using a [`Logger`](crate::Logger) or one
+/// of its handles.
+#[derive(Debug)]
+pub enum LoggerError {
+    /// The buffer has no room left: it holds `capacity` messages that have
+    /// not been read by the whole quorum yet, or, when `max_bytes` is set,
+    /// as many bytes as its byte budget of `max_bytes` allows.
+    Full { capacity: usize, max_bytes: Option<usize> },
+    /// The messages take up `bytes`, more than the whole byte budget of
+    /// `max_bytes`, so they would never fit.
+    TooLarge { bytes: usize, max_bytes: usize },
+    /// The buffer was full and the message was thrown away, as asked for by
+    /// [`Backpressure::DropNewest`](crate::Backpressure::DropNewest).
+    Dropped,
+    /// All `num_readers` reader slots are taken.
+    ReaderRejected { num_readers: usize },
+    /// The durable reader `name` is already subscribed.
+    ReaderInUse { name: String },
+    /// Another [`Writer`](crate::Writer) is still alive.
+    WriterTaken,
+    /// The logger already has a topic called `name`.
+    TopicExists { name: String },
+    /// The logger has no topic called `name`.
+    UnknownTopic { name: String },
+    /// The message at `offset` is not waiting to be acknowledged by this
+    /// reader: it was acknowledged already, or delivered to another one,
+    /// possibly of another logger or topic.
+    UnknownDelivery { offset: u64 },
+    /// The logger was closed. Writers get this straight away, readers once
+    /// they have read everything that was written before the close.
+    Closed,
+    /// The system's random number generator could not produce a key.
+    KeyGeneration,
+    /// The key bytes are not a valid key for the algorithm they were given
+    /// for.
+    InvalidKey,
+    /// The message at `offset` could not be decrypted: it was tampered with
+    /// or encrypted with another key.
+    Decryption { offset: u64 },
+    /// The logger was not built with [`Builder::audit`](crate::Builder::audit),
+    /// so it keeps no Merkle tree.
+    Unaudited,
+    /// A proof was asked for `index`, but the Merkle tree only has `size`
+    /// leaves.
+    OutOfRange { index: u64, size: u64 },
+    /// Reading or writing the log's files failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for LoggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoggerError::Full { capacity, max_bytes: None } => {
+                write!(f, "buffer is full ({} unread messages)", capacity)
+            }
+            LoggerError::Full { max_bytes: Some(max_bytes), .. } => {
+                write!(f, "buffer is full ({} bytes of unread messages)", max_bytes)
+            }
+            LoggerError::TooLarge { bytes, max_bytes } => {
+                write!(f, "{} bytes is more than the buffer's budget of {} bytes", bytes, max_bytes)
+            }
+            LoggerError::Dropped => write!(f, "buffer is full, message dropped"),
+            LoggerError::ReaderRejected { num_readers } => {
+                write!(f, "all {} reader slots are in use", num_readers)
+            }
+            LoggerError::ReaderInUse { name } => {
+                write!(f, "reader {:?} is already subscribed", name)
+            }
+            LoggerError::WriterTaken => write!(f, "the logger already has a writer"),
+            LoggerError::TopicExists { name } => write!(f, "topic {:?} already exists", name),
+            LoggerError::UnknownTopic { name } => write!(f, "there is no topic {:?}", name),
+            LoggerError::UnknownDelivery { offset } => {
+                write!(f, "message {} is not waiting to be acknowledged", offset)
+            }
+            LoggerError::Closed => write!(f, "the logger is closed"),
+            LoggerError::KeyGeneration => write!(f, "failed to generate a random key"),
+            LoggerError::InvalidKey => write!(f, "not a valid key for the algorithm"),
+            LoggerError::Decryption { offset } => {
+                write!(f, "message {} failed to decrypt", offset)
+            }
+            LoggerError::Unaudited => write!(f, "the logger keeps no Merkle tree"),
+            LoggerError::OutOfRange { index, size } => {
+                write!(f, "{} is out of range for a Merkle tree of {} leaves", index, size)
+            }
+            LoggerError::Io(e) => write!(f, "i/o error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoggerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoggerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoggerError {
+    fn from(e: io::Error) -> Self {
+        LoggerError::Io(e)
+    }
+}
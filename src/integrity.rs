@@ -0,0 +1,132 @@
+use crate::LoggerError;
+use std::fmt;
+use std::path::Path;
+
+/// How a logger protects its messages. The writer signs every message and
+/// readers verify it, so the cost is paid on both sides: pick the cheapest
+/// check that gives the guarantees you need.
+///
+/// [`Keys`](crate::Keys), the default, signs with HMAC or Ed25519 and
+/// supports key rotation. [`Crc32c`] only detects corruption, and
+/// [`NoIntegrity`] skips the check altogether.
+pub trait Integrity: Send + Sync + 'static {
+    /// What is kept with every message, handed out as [`Response::hash`].
+    ///
+    /// [`Response::hash`]: crate::Response::hash
+    type Tag: Clone + Default + fmt::Debug + Send + Sync;
+
+    fn sign(&self, data: &[u8]) -> Self::Tag;
+
+    fn verify(&self, data: &[u8], tag: &Self::Tag) -> bool;
+
+    /// The tag as it is written to the segment files and chained to the
+    /// next message.
+    fn encode(tag: &Self::Tag) -> Vec<u8>;
+
+    /// Reads back a tag written by [`Integrity::encode`].
+    fn decode(bytes: &[u8]) -> Option<Self::Tag>;
+
+    /// Bytes `tag` keeps on the heap, which count toward a logger's
+    /// [`Builder::max_bytes`](crate::Builder::max_bytes). None by default.
+    fn tag_bytes(_tag: &Self::Tag) -> usize {
+        0
+    }
+
+    /// Called once when the logger is created, with the log's directory if it
+    /// is persistent, so keys can be loaded or generated.
+    fn open(&mut self, _dir: Option<&Path>) -> Result<(), LoggerError> {
+        Ok(())
+    }
+}
+
+/// CRC-32C (Castagnoli) checksums. Catches corrupted messages at a fraction
+/// of the cost of a MAC, but anyone can forge them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc32c;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32C of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+impl Integrity for Crc32c {
+    type Tag = u32;
+
+    fn sign(&self, data: &[u8]) -> u32 {
+        crc32c(data)
+    }
+
+    fn verify(&self, data: &[u8], tag: &u32) -> bool {
+        crc32c(data) == *tag
+    }
+
+    fn encode(tag: &u32) -> Vec<u8> {
+        tag.to_le_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<u32> {
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// No integrity check: messages carry no tag and are always valid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoIntegrity;
+
+impl Integrity for NoIntegrity {
+    type Tag = ();
+
+    fn sign(&self, _data: &[u8]) {}
+
+    fn verify(&self, _data: &[u8], _tag: &()) -> bool {
+        true
+    }
+
+    fn encode(_tag: &()) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn decode(_bytes: &[u8]) -> Option<()> {
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_matches_known_values() {
+        // RFC 3720, appendix B.4
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(crc32c(&[0xff; 32]), 0x62a8_ab43);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+
+        let tag = Crc32c.sign(b"data");
+        assert!(Crc32c.verify(b"data", &tag));
+        assert!(!Crc32c.verify(b"dato", &tag));
+        assert_eq!(Crc32c::decode(&Crc32c::encode(&tag)), Some(tag));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::integrity::Integrity;
+use crate::LoggerError;
+use ring::hmac;
+use ring::rand::{self, SecureRandom};
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Length of the HMAC keys generated by the logger.
+pub const KEY_LEN: usize = 48;
+
+/// How messages are signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    /// HMAC-SHA256. Checking a message takes the same key as signing it,
+    /// so every reader could also forge messages.
+    #[default]
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+    /// Ed25519 signatures. Only the writer holds the private key; messages
+    /// are checked with the public key. Keys are PKCS#8 documents.
+    Ed25519,
+}
+
+/// Checks an Ed25519 signature on a message from a logger that does not
+/// chain its messages, with the public key from
+/// [`Logger::public_key`](crate::Logger::public_key).
+pub fn verify_signature(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(data, signature)
+        .is_ok()
+}
+
+/// Fills a new HMAC key from the system's secure random number generator.
+pub fn generate_key() -> Result<Vec<u8>, LoggerError> {
+    random_bytes(KEY_LEN)
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>, LoggerError> {
+    let mut buf = vec![0u8; len];
+    rand::SystemRandom::new()
+        .fill(&mut buf)
+        .map_err(|_| LoggerError::KeyGeneration)?;
+    Ok(buf)
+}
+
+/// Generates a key for `algorithm`, in the form [`save_key`] saves it in.
+pub fn generate_key_for(algorithm: Algorithm) -> Result<Vec<u8>, LoggerError> {
+    match algorithm {
+        Algorithm::HmacSha256 | Algorithm::HmacSha384 => generate_key(),
+        Algorithm::HmacSha512 => random_bytes(64),
+        Algorithm::Ed25519 => Ed25519KeyPair::generate_pkcs8(&rand::SystemRandom::new())
+            .map(|pkcs8| pkcs8.as_ref().to_vec())
+            .map_err(|_| LoggerError::KeyGeneration),
+    }
+}
+
+/// A key messages are signed with.
+#[derive(Debug, Clone)]
+pub(crate) enum Key {
+    Hmac(hmac::Key),
+    Ed25519(Arc<Ed25519KeyPair>),
+}
+
+impl Key {
+    /// Makes a key for `algorithm` out of bytes saved with [`save_key`].
+    pub(crate) fn from_bytes(algorithm: Algorithm, bytes: &[u8]) -> Result<Self, LoggerError> {
+        let hmac = |algorithm| Ok(Key::Hmac(hmac::Key::new(algorithm, bytes)));
+        match algorithm {
+            Algorithm::HmacSha256 => hmac(hmac::HMAC_SHA256),
+            Algorithm::HmacSha384 => hmac(hmac::HMAC_SHA384),
+            Algorithm::HmacSha512 => hmac(hmac::HMAC_SHA512),
+            Algorithm::Ed25519 => Ed25519KeyPair::from_pkcs8(bytes)
+                .map(|pair| Key::Ed25519(Arc::new(pair)))
+                .map_err(|_| LoggerError::InvalidKey),
+        }
+    }
+
+    pub(crate) fn algorithm(&self) -> Algorithm {
+        match self {
+            Key::Hmac(key) if key.algorithm() == hmac::HMAC_SHA384 => Algorithm::HmacSha384,
+            Key::Hmac(key) if key.algorithm() == hmac::HMAC_SHA512 => Algorithm::HmacSha512,
+            Key::Hmac(_) => Algorithm::HmacSha256,
+            Key::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Key::Hmac(key) => hmac::sign(key, data).as_ref().to_vec(),
+            Key::Ed25519(pair) => pair.sign(data).as_ref().to_vec(),
+        }
+    }
+
+    fn verify(&self, data: &[u8], tag: &[u8]) -> bool {
+        match self {
+            Key::Hmac(key) => hmac::verify(key, data, tag).is_ok(),
+            // only the public half is needed to check a signature
+            Key::Ed25519(pair) => verify_signature(pair.public_key().as_ref(), data, tag),
+        }
+    }
+}
+
+/// Reads a key saved with [`save_key`].
+pub fn load_key<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, LoggerError> {
+    let buf = fs::read(path)?;
+    if buf.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, "key file is empty").into());
+    }
+    Ok(buf)
+}
+
+/// Writes `key` to a new file at `path` that only the owner can read. Fails
+/// if the file already exists, so a key is never overwritten by accident.
+pub fn save_key<P: AsRef<Path>>(path: P, key: &[u8]) -> Result<(), LoggerError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(key)?;
+    Ok(())
+}
+
+/// The keys a logger signs and verifies messages with. Messages are always
+/// signed with the newest key; older keys are kept until every message
+/// signed with them is gone, so those can still be verified.
+pub(crate) struct KeyRing {
+    algorithm: Algorithm,
+    // ordered by id, the last one is the current key
+    keys: Vec<(u32, Key)>,
+    // rotated keys are saved as `<base>.<id>` when set
+    base: Option<PathBuf>,
+}
+
+impl KeyRing {
+    /// A ring holding just `key`, with id 0.
+    pub(crate) fn new(key: Key) -> Self {
+        KeyRing { algorithm: key.algorithm(), keys: vec![(0, key)], base: None }
+    }
+
+    /// Loads every key rotated in after `base`. The first key (id 0) is
+    /// `first` if given, or else the key saved at `base`, which is created
+    /// the first time.
+    pub(crate) fn load(
+        algorithm: Algorithm,
+        base: &Path,
+        first: Option<Key>,
+    ) -> Result<Self, LoggerError> {
+        let first = match first {
+            Some(key) => key,
+            None => Key::from_bytes(algorithm, &load_or_create(base, algorithm)?)?,
+        };
+        let mut ring = KeyRing::new(first);
+        let algorithm = ring.algorithm;
+        ring.base = Some(base.to_path_buf());
+
+        let (dir, prefix) = rotated_prefix(base);
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let id = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|id| id.parse::<u32>().ok());
+            if let Some(id) = id {
+                rotated.push((id, Key::from_bytes(algorithm, &load_key(entry.path())?)?));
+            }
+        }
+        rotated.sort_by_key(|(id, _)| *id);
+        ring.keys.extend(rotated);
+        Ok(ring)
+    }
+
+    pub(crate) fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn current(&self) -> u32 {
+        self.keys.last().unwrap().0
+    }
+
+    /// Public key of the current key, if it has one.
+    pub(crate) fn public_key(&self) -> Option<Vec<u8>> {
+        match &self.keys.last().unwrap().1 {
+            Key::Hmac(_) => None,
+            Key::Ed25519(pair) => Some(pair.public_key().as_ref().to_vec()),
+        }
+    }
+
+    pub(crate) fn ids(&self) -> Vec<u32> {
+        self.keys.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Signs `data` with the current key, returning the key's id and the tag.
+    pub(crate) fn sign(&self, data: &[u8]) -> (u32, Vec<u8>) {
+        let (id, key) = self.keys.last().unwrap();
+        (*id, key.sign(data))
+    }
+
+    /// Checks `tag` with the key it was signed with. Fails if that key has
+    /// been purged.
+    pub(crate) fn verify(&self, id: u32, data: &[u8], tag: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .find(|(key_id, _)| *key_id == id)
+            .is_some_and(|(_, key)| key.verify(data, tag))
+    }
+
+    /// Makes `bytes` the current key, saving it if the ring has a base file.
+    pub(crate) fn rotate(&mut self, bytes: &[u8]) -> Result<u32, LoggerError> {
+        let id = self.current() + 1;
+        let key = Key::from_bytes(self.algorithm, bytes)?;
+        if let Some(base) = &self.base {
+            save_key(rotated_path(base, id), bytes)?;
+        }
+        self.keys.push((id, key));
+        Ok(id)
+    }
+
+    /// Removes every old key `in_use` says nothing needs anymore. The
+    /// current key is always kept, as is the base key file.
+    pub(crate) fn purge(&mut self, in_use: impl Fn(u32) -> bool) -> Result<Vec<u32>, LoggerError> {
+        let current = self.current();
+        let mut purged = Vec::new();
+        for (id, _) in &self.keys {
+            if *id != current && !in_use(*id) {
+                if let (Some(base), true) = (&self.base, *id > 0) {
+                    fs::remove_file(rotated_path(base, *id))?;
+                }
+                purged.push(*id);
+            }
+        }
+        self.keys.retain(|(id, _)| !purged.contains(id));
+        Ok(purged)
+    }
+}
+
+/// The tag of a message signed by [`Keys`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    /// Id of the key the message was signed with.
+    pub key_id: u32,
+    /// The HMAC tag or Ed25519 signature.
+    pub bytes: Vec<u8>,
+}
+
+/// The default [`Integrity`]: messages are signed with HMAC or Ed25519 keys,
+/// see [`Algorithm`], that can be rotated while the logger runs. The keys
+/// are picked on the [`Builder`](crate::Builder) and loaded when the logger
+/// is created.
+pub struct Keys {
+    algorithm: Algorithm,
+    key: Option<Key>,
+    key_bytes: Option<Vec<u8>>,
+    key_file: Option<PathBuf>,
+    // set by `open`
+    ring: Option<RwLock<KeyRing>>,
+}
+
+impl Keys {
+    pub(crate) fn new() -> Self {
+        Keys {
+            algorithm: Algorithm::default(),
+            key: None,
+            key_bytes: None,
+            key_file: None,
+            ring: None,
+        }
+    }
+
+    pub(crate) fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
+    }
+
+    /// Uses `key`, or the key made from `bytes`, or the one saved at `file`.
+    pub(crate) fn set_key(&mut self, key: Option<Key>, bytes: Option<&[u8]>, file: Option<PathBuf>) {
+        if let Some(key) = &key {
+            self.algorithm = key.algorithm();
+        }
+        self.key = key;
+        self.key_bytes = bytes.map(<[u8]>::to_vec);
+        self.key_file = file;
+    }
+
+    // The key given with `set_key`, if any.
+    fn first_key(&self) -> Result<Option<Key>, LoggerError> {
+        match (&self.key, &self.key_bytes) {
+            (Some(key), _) => Ok(Some(key.clone())),
+            (None, Some(bytes)) => Key::from_bytes(self.algorithm, bytes).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub(crate) fn ring(&self) -> &RwLock<KeyRing> {
+        self.ring.as_ref().expect("keys are loaded when the logger is created")
+    }
+}
+
+// Only a builder's keys are cloned, and those have not been loaded yet.
+impl Clone for Keys {
+    fn clone(&self) -> Self {
+        Keys {
+            algorithm: self.algorithm,
+            key: self.key.clone(),
+            key_bytes: self.key_bytes.clone(),
+            key_file: self.key_file.clone(),
+            ring: None,
+        }
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("algorithm", &self.algorithm)
+            .field("key_file", &self.key_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Integrity for Keys {
+    type Tag = Signature;
+
+    fn sign(&self, data: &[u8]) -> Signature {
+        let (key_id, bytes) = self.ring().read().unwrap().sign(data);
+        Signature { key_id, bytes }
+    }
+
+    fn verify(&self, data: &[u8], tag: &Signature) -> bool {
+        self.ring().read().unwrap().verify(tag.key_id, data, &tag.bytes)
+    }
+
+    fn encode(tag: &Signature) -> Vec<u8> {
+        let mut buf = tag.key_id.to_le_bytes().to_vec();
+        buf.extend_from_slice(&tag.bytes);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Signature> {
+        let key_id = u32::from_le_bytes(bytes.get(..4)?.try_into().unwrap());
+        Some(Signature { key_id, bytes: bytes[4..].to_vec() })
+    }
+
+    fn tag_bytes(tag: &Signature) -> usize {
+        tag.bytes.capacity()
+    }
+
+    /// Without a key, a random one is generated. A persistent logger's keys
+    /// have to outlive the process, or recovered messages could not be
+    /// checked, so without a key file they are kept next to the log.
+    fn open(&mut self, dir: Option<&Path>) -> Result<(), LoggerError> {
+        let first = self.first_key()?;
+        let ring = match (dir, &self.key_file) {
+            (_, Some(path)) if first.is_none() => KeyRing::load(self.algorithm, path, None)?,
+            (Some(dir), _) => KeyRing::load(self.algorithm, &dir.join("key"), first)?,
+            (None, _) => match first {
+                Some(key) => KeyRing::new(key),
+                None => KeyRing::new(Key::from_bytes(self.algorithm, &generate_key_for(self.algorithm)?)?),
+            },
+        };
+        self.ring = Some(RwLock::new(ring));
+        Ok(())
+    }
+}
+
+// The directory rotated keys live in, and the file name prefix they share.
+fn rotated_prefix(base: &Path) -> (&Path, String) {
+    let dir = match base.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = base.file_name().and_then(|n| n.to_str()).unwrap_or("key");
+    (dir, format!("{}.", name))
+}
+
+fn rotated_path(base: &Path, id: u32) -> PathBuf {
+    let (dir, prefix) = rotated_prefix(base);
+    dir.join(format!("{}{}", prefix, id))
+}
+
+// Loads the key at `path`, generating and saving one the first time.
+pub(crate) fn load_or_create(path: &Path, algorithm: Algorithm) -> Result<Vec<u8>, LoggerError> {
+    match load_key(path) {
+        Err(LoggerError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+            let key = generate_key_for(algorithm)?;
+            save_key(path, &key)?;
+            Ok(key)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    #[test]
+    fn rotated_keys_are_saved_and_purged() {
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let base = dir.0.join("key");
+
+        let mut ring = KeyRing::load(Algorithm::HmacSha256, &base, None).unwrap();
+        let (old, old_tag) = ring.sign(b"data");
+        let new = ring.rotate(&generate_key().unwrap()).unwrap();
+        assert_eq!((old, new), (0, 1));
+        let (id, tag) = ring.sign(b"data");
+        assert_eq!(id, 1);
+        assert!(ring.verify(0, b"data", &old_tag));
+        assert!(ring.verify(1, b"data", &tag));
+        assert!(!ring.verify(0, b"data", &tag));
+
+        ring.rotate(&generate_key().unwrap()).unwrap();
+        let reloaded = KeyRing::load(Algorithm::HmacSha256, &base, None).unwrap();
+        assert_eq!(reloaded.ids(), vec![0, 1, 2]);
+        assert!(reloaded.verify(1, b"data", &tag));
+
+        // key 1 is still needed, key 0 is not
+        assert_eq!(ring.purge(|id| id == 1).unwrap(), vec![0]);
+        assert_eq!(ring.purge(|_| false).unwrap(), vec![1]);
+        assert_eq!(ring.ids(), vec![2]);
+        assert!(!ring.verify(1, b"data", &tag));
+        assert_eq!(KeyRing::load(Algorithm::HmacSha256, &base, None).unwrap().ids(), vec![0, 2]);
+    }
+
+    #[test]
+    fn ed25519_keys_verify_with_the_public_key() {
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let base = dir.0.join("key");
+
+        let ring = KeyRing::load(Algorithm::Ed25519, &base, None).unwrap();
+        let (_, signature) = ring.sign(b"data");
+        let public_key = ring.public_key().unwrap();
+        assert!(verify_signature(&public_key, b"data", &signature));
+        assert!(!verify_signature(&public_key, b"other", &signature));
+
+        // the saved key is the same one, and is no HMAC key
+        let reloaded = KeyRing::load(Algorithm::Ed25519, &base, None).unwrap();
+        assert_eq!(reloaded.public_key(), Some(public_key));
+        let mut ring = ring;
+        assert!(matches!(ring.rotate(&generate_key().unwrap()), Err(LoggerError::InvalidKey)));
+    }
+
+    #[test]
+    fn key_file_is_created_once() {
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("key");
+
+        let key = load_or_create(&path, Algorithm::HmacSha256).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(load_or_create(&path, Algorithm::HmacSha256).unwrap(), key);
+        assert!(save_key(&path, b"other").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
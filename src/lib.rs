@@ -1,185 +1,3054 @@
-use ring::rand::SecureRandom;
-use ring::{hmac, rand};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering::{AcqRel, SeqCst}};
-use std::sync::RwLock;
-use std::thread::{self, ThreadId};
+mod builder;
+mod cipher;
+mod delivery;
+mod error;
+mod integrity;
+mod key;
+mod merkle;
+mod observer;
+mod stats;
+mod storage;
+mod topic;
+mod wait;
+
+pub use builder::{Backpressure, Builder};
+pub use cipher::{Cipher, EncryptionKey, ENCRYPTION_KEY_LEN};
+pub use delivery::DeliveryToken;
+pub use error::LoggerError;
+pub use integrity::{crc32c, Crc32c, Integrity, NoIntegrity};
+pub use key::{
+    generate_key, generate_key_for, load_key, save_key, verify_signature, Algorithm, Keys, Signature, KEY_LEN,
+};
+pub use merkle::{leaf_hash, verify_checkpoint, verify_consistency, verify_inclusion, Checkpoint, Hash};
+pub use observer::{NoObserver, Observer};
+pub use stats::{ReaderStats, Stats};
+pub use topic::{TopicReader, TopicResponse};
+#[cfg(feature = "tracing")]
+pub use observer::Tracing;
+pub use wait::WaitStrategy;
+
+use delivery::InFlight;
+use merkle::Audit;
+use stats::Counters;
+use std::borrow::Cow;
+use std::mem;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst}};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::{Duration, Instant};
+use storage::{CursorFile, Entry, Recovered, Storage};
+use topic::Topics;
+use wait::Notify;
 
 #[derive(Debug)]
-pub struct Message {
-    bytes: Vec<u8>,
-    hash: hmac::Tag,
+pub struct Message<T> {
+    // position of the message in the log. Offsets start at 0, grow by one for
+    // every write and are never reused, so they stay valid after eviction.
+    offset: u64,
+    // shared with every reader that reads the message
+    bytes: Arc<[u8]>,
+    // `None` for a message missing from a recovered log
+    hash: Option<T>,
+    // hash of the message before this one in a chained log, empty otherwise
+    prev: Vec<u8>,
     readers: AtomicUsize,
 }
 
-impl Clone for Message {
+impl<T: Clone> Clone for Message<T> {
     fn clone(&self) -> Self {
-        Message { 
-            bytes: self.bytes.clone(), 
-            hash: self.hash, 
+        Message {
+            offset: self.offset,
+            bytes: self.bytes.clone(),
+            hash: self.hash.clone(),
+            prev: self.prev.clone(),
             readers: AtomicUsize::new(self.readers.load(SeqCst)) }
     }
 }
 
-pub struct Logger {
+// One of the `num_readers` positions that make up the quorum. A slot keeps its
+// offset when its reader unsubscribes, so whoever takes it over next carries
+// on from there and every message is still read exactly once per slot.
+struct ReaderSlot {
+    // readers subscribed in the slot: at most one, unless a consumer group
+    // is using it
+    members: AtomicUsize,
+    // set while the slot is used by a consumer group rather than a single
+    // durable reader
+    group: AtomicBool,
+    // offset of the next message this slot will read
+    offset: AtomicU64,
+    // set once the slot belongs to a durable reader or a group; plain
+    // subscribers never take a named slot
+    name: OnceLock<String>,
+    // held while a message is claimed for the slot, so the members of a
+    // group never get the same message
+    state: Mutex<SlotState>,
+}
+
+#[derive(Default)]
+struct SlotState {
+    // where a durable reader or group on a persistent logger checkpoints
+    // `offset`
+    cursor: Option<CursorFile>,
+    // offset and hash of the last message read in the slot, to check the
+    // chain against
+    last: Option<(u64, Vec<u8>)>,
+    // messages handed out but not acknowledged yet
+    in_flight: InFlight,
+}
+
+impl SlotState {
+    // Whether the message at `offset`, chained to `prev`, does not follow on
+    // from the last one read in the slot. The first message read in a slot
+    // is taken on trust.
+    fn chain_break(&mut self, offset: u64, prev: &[u8], tag: Vec<u8>) -> bool {
+        let broken = self
+            .last
+            .as_ref()
+            .is_some_and(|(last_offset, last_hash)| offset != last_offset + 1 || prev != last_hash.as_slice());
+        self.last = Some((offset, tag));
+        broken
+    }
+}
+
+/// A slot in the ring. Offset `o` always lives in slot `o % size`; readers
+/// compare the message's offset with the one they asked for to tell a live
+/// message from one that has been evicted and replaced.
+type Slot<T> = RwLock<Option<Message<T>>>;
+
+// Hands out `Shared::log`.
+static NEXT_LOG: AtomicU64 = AtomicU64::new(0);
+
+// State shared by the logger and every handle created from it.
+struct Shared<I: Integrity> {
+    // tells this log apart from every other logger and topic in the process,
+    // so a delivery token can only be acknowledged where it came from
+    log: u64,
     num_readers: usize,
-    readers: RwLock<HashMap<ThreadId, usize>>,
-    messages: Vec<Message>,
+    readers: Box<[ReaderSlot]>,
+    // held while a durable reader is looking for its slot
+    naming: Mutex<()>,
+    // set while a `Writer` exists, unless there can be many of them
+    writer_taken: AtomicBool,
+    multi_producer: bool,
+    // held by a writer for the whole write, so concurrent writers take turns
+    // and offsets are handed out in the order the writes are published
+    sequencer: Mutex<()>,
+    closed: AtomicBool,
+    slots: Box<[Slot<I::Tag>]>,
+    // offset of the oldest retained message
+    head: AtomicU64,
+    // offset the next message will be written to
+    tail: AtomicU64,
+    // readers parked waiting for the tail to move
+    published: Notify,
+    // a writer parked waiting for the head to move
+    freed: Notify,
     size: usize,
-    key: hmac::Key,
+    // the byte budget set with `Builder::max_bytes`
+    max_bytes: Option<usize>,
+    // bytes taken up by the retained messages, see `message_bytes`
+    bytes: AtomicUsize,
+    backpressure: Backpressure,
+    // messages thrown away by `Backpressure::DropNewest`
+    dropped: AtomicU64,
+    // shared by a logger and its topics
+    integrity: Arc<I>,
+    // whether every hash also covers the offset and the hash before it
+    chained: bool,
+    // how long a message may go unacknowledged before it is delivered
+    // again, for loggers built with `Builder::visibility_timeout`
+    visibility_timeout: Option<Duration>,
+    // encoded hash of the newest message, which the next one is chained to.
+    // Held by the writer for the whole write.
+    last_hash: Mutex<Vec<u8>>,
+    // encrypts payloads, for loggers built with `Builder::encryption`
+    encryption: Option<EncryptionKey>,
+    // the Merkle tree, for loggers built with `Builder::audit`
+    audit: Option<Mutex<Audit>>,
+    // where messages are persisted, for loggers created with `Logger::open`
+    storage: Option<Storage>,
+    observer: Arc<dyn Observer>,
+    counters: Counters,
+    // the logger a topic was created on, `None` for the logger itself
+    parent: Option<Weak<Shared<I>>>,
+    // the topic's name, passed to the observer
+    name: Option<String>,
+    // the topics created on this logger, `None` for a topic
+    topics: Option<Topics<I>>,
 }
 
-impl Logger {
-    pub fn new(num_readers: usize, size: usize) -> Self {
-        // Create key for signing SHA256 hashes
-        let mut buf = [0u8; 48];
-        let rng = rand::SystemRandom::new();
-        let _ = rng.fill(&mut buf);
-        let key = hmac::Key::new(hmac::HMAC_SHA256, &buf);
+impl<I: Integrity> Shared<I> {
+    fn slot(&self, offset: u64) -> &Slot<I::Tag> {
+        &self.slots[(offset % self.size as u64) as usize]
+    }
 
-        Self {
-            num_readers,
-            readers: RwLock::new(HashMap::with_capacity(num_readers)),
-            messages: Vec::with_capacity(size),
-            size,
-            key,
+    // Drop every message at the head that has been read by the whole quorum.
+    // Quorum is always reached in order because each reader reads in order,
+    // so this stops at the first message that is still needed.
+    fn advance_head(&self) {
+        let mut advanced = false;
+        loop {
+            let head = self.head.load(Acquire);
+            if head >= self.tail.load(Acquire)
+                || !self.evict(head, |m| m.readers.load(Acquire) >= self.num_readers)
+            {
+                break;
+            }
+            advanced = true;
         }
+        if advanced {
+            self.head_moved();
+        }
+    }
+
+    // Drops the message at `head` and moves the head past it, as long as
+    // `evictable` agrees and nobody else got there first.
+    fn evict(&self, head: u64, evictable: impl Fn(&Message<I::Tag>) -> bool) -> bool {
+        let mut slot = self.slot(head).write().unwrap();
+        match &*slot {
+            Some(m) if m.offset == head && evictable(m) => {
+                self.bytes.fetch_sub(message_bytes::<I>(m), AcqRel);
+                let unread = m.readers.load(Acquire) < self.num_readers;
+                self.counters.evicted(unread);
+                self.observer.on_evict(self.name.as_deref(), head, unread);
+                *slot = None;
+                self.head.store(head + 1, Release);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Whether `n` messages taking up `bytes` can't be written at `tail`
+    // without going over the buffer's size or byte budget.
+    fn is_full(&self, tail: u64, n: u64, bytes: usize) -> bool {
+        tail + n - self.head.load(Acquire) > self.size as u64
+            || self.max_bytes.is_some_and(|max_bytes| self.bytes.load(Acquire) + bytes > max_bytes)
+    }
+
+    // The error for a buffer without room for `n` more messages at `tail`,
+    // telling whether it is the byte budget that ran out.
+    fn full(&self, tail: u64, n: u64) -> LoggerError {
+        let counted = tail + n - self.head.load(Acquire) > self.size as u64;
+        LoggerError::Full { capacity: self.size, max_bytes: if counted { None } else { self.max_bytes } }
+    }
+
+    // Reports an error that is about to be returned to the caller.
+    fn reject(&self, error: LoggerError) -> LoggerError {
+        if matches!(error, LoggerError::Full { .. } | LoggerError::Dropped) {
+            self.counters.rejected();
+        }
+        self.observer.on_reject(self.name.as_deref(), &error);
+        error
+    }
+
+    fn head_moved(&self) {
+        if let Some(storage) = &self.storage {
+            let head = self.head.load(Acquire);
+            // the message has already been handed out, so all that is lost if
+            // this fails is that it will come back after a restart
+            if let Err(e) = storage.set_head(head) {
+                self.observer.on_error(self.name.as_deref(), &LoggerError::Io(e));
+            }
+        }
+        self.freed.notify();
     }
 }
 
-pub struct Response {
-    pub message: Vec<u8>,
-    pub hash: hmac::Tag,
-    pub is_valid: bool,
+/// The logger owns the buffer. Messages are written through the single
+/// [`Writer`] and read through [`Reader`] handles, all of which can be moved to
+/// other threads. Reads take no logger-wide lock: `head` and `tail` are
+/// atomics and each slot has its own lock, which is only held long enough to
+/// fill, clone or clear that one message. Writes are serialized by a lock
+/// held for the whole write, including any wait for room in a full buffer.
+pub struct Logger<I: Integrity = Keys> {
+    shared: Arc<Shared<I>>,
+}
+
+impl<I: Integrity> Clone for Logger<I> {
+    fn clone(&self) -> Self {
+        Logger { shared: self.shared.clone() }
+    }
 }
 
-pub trait Log {
-    fn write(&mut self, data: &[u8]) -> Result<(), usize>;
-    fn read(&mut self) -> Result<Option<Response>, usize>;
+impl Logger {
+    /// Creates an in-memory logger signing messages with a random key.
+    pub fn new(num_readers: usize, size: usize) -> Result<Self, LoggerError> {
+        Self::builder(num_readers, size).build()
+    }
+
+    /// Opens a persistent logger backed by the segment files in `dir`. See
+    /// [`Builder::open`].
+    pub fn open<P: AsRef<Path>>(dir: P, num_readers: usize, size: usize) -> Result<Self, LoggerError> {
+        Self::builder(num_readers, size).open(dir)
+    }
+
+    /// Starts configuring a logger that drops a message once `num_readers`
+    /// readers have read it and holds at most `size` messages.
+    pub fn builder(num_readers: usize, size: usize) -> Builder {
+        Builder::new(num_readers, size)
+    }
+
+    /// Starts signing new messages with a freshly generated key and returns
+    /// its id. Messages signed with older keys can still be verified until
+    /// those keys are purged with [`Logger::purge_keys`].
+    pub fn rotate_key(&self) -> Result<u32, LoggerError> {
+        let algorithm = self.algorithm();
+        self.rotate_key_to(&generate_key_for(algorithm)?)
+    }
+
+    /// Like [`Logger::rotate_key`], but with a key of the caller's choosing.
+    /// A persistent logger saves the key next to its key file.
+    pub fn rotate_key_to(&self, key: &[u8]) -> Result<u32, LoggerError> {
+        self.shared.integrity.ring().write().unwrap().rotate(key)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.shared.integrity.ring().read().unwrap().algorithm()
+    }
+
+    /// The Ed25519 public key messages are currently signed with, which is
+    /// all a reader in another process needs to check them. `None` for HMAC.
+    pub fn public_key(&self) -> Option<Vec<u8>> {
+        self.shared.integrity.ring().read().unwrap().public_key()
+    }
+
+    /// Id of the key new messages are signed with.
+    pub fn key_id(&self) -> u32 {
+        self.shared.integrity.ring().read().unwrap().current()
+    }
+
+    /// Ids of every key the logger still holds, oldest first.
+    pub fn key_ids(&self) -> Vec<u32> {
+        self.shared.integrity.ring().read().unwrap().ids()
+    }
+
+    /// Forgets the retired keys that no retained message was signed with and
+    /// returns their ids. Rotated key files are deleted; the logger's own key
+    /// file is left alone.
+    ///
+    /// Topics share their logger's keys, so the messages of every topic are
+    /// taken into account.
+    pub fn purge_keys(&self) -> Result<Vec<u32>, LoggerError> {
+        let root = self.root().unwrap_or_else(|| self.clone());
+        // no topic can be created while we go through them, and purges take
+        // the writers' locks in the same order
+        let topics = root.shared.topics.as_ref().map(|topics| topics.loggers.lock().unwrap());
+        let loggers: Vec<_> = std::iter::once(&root).chain(topics.iter().flat_map(|t| t.values())).collect();
+        // keep the writers out, so nothing can be signed with a key we are
+        // about to drop while its message is not in its slot yet
+        let _writing: Vec<_> = loggers.iter().map(|l| l.shared.last_hash.lock().unwrap()).collect();
+        let mut keys = self.shared.integrity.ring().write().unwrap();
+        let in_use: HashSet<u32> = loggers
+            .iter()
+            .flat_map(|l| l.shared.slots.iter())
+            .filter_map(|slot| {
+                let slot = slot.read().unwrap();
+                slot.as_ref().and_then(|m| m.hash.as_ref()).map(|hash| hash.key_id)
+            })
+            .collect();
+        keys.purge(|id| in_use.contains(&id))
+    }
 }
 
-impl Log for Logger {
-    /// Error Code 1 means the buffer is full
-    fn write(&mut self, data: &[u8]) -> Result<(), usize> {
-        let thread_id = thread::current().id();
-        eprintln!("writer: thread {:?} is writing...", thread_id);
-        if self.messages.len() >= self.size {
-            eprintln!("writer: buffer is full!");
-            return Err(1);
-        }
-        let hash = hmac::sign(&self.key, data);
-        let message = Message {
-            readers: AtomicUsize::new(0),
-            bytes: data.to_vec(),
-            hash,
+impl<I: Integrity> Logger<I> {
+    pub(crate) fn from_parts(
+        config: Builder<()>,
+        integrity: Arc<I>,
+        parent: Option<(Weak<Shared<I>>, String)>,
+        audit: Option<Audit>,
+        storage: Option<(Storage, Recovered)>,
+    ) -> Self {
+        let topics = parent.is_none().then(|| Topics::new(config.clone()));
+        let (parent, name) = parent.unzip();
+        let Builder {
+            num_readers,
+            size,
+            max_bytes,
+            backpressure,
+            chained,
+            multi_producer,
+            visibility_timeout,
+            encryption,
+            observer,
+            ..
+        } = config;
+        let (storage, recovered) = match storage {
+            Some((storage, recovered)) => (Some(storage), recovered),
+            None => (
+                None,
+                Recovered { head: 0, tail: 0, records: Vec::new(), last_tag: Vec::new(), cursors: Vec::new() },
+            ),
         };
 
-        self.messages.push(message);
+        let slots: Box<[Slot<I::Tag>]> = (0..size).map(|_| RwLock::new(None)).collect();
+        let mut bytes = 0;
+        for record in recovered.records {
+            // plain readers start again from the head, so only the durable
+            // readers that got past this message have read it
+            let readers = recovered
+                .cursors
+                .iter()
+                .filter(|(_, offset)| *offset > record.offset)
+                .count();
+            let message = Message {
+                offset: record.offset,
+                bytes: record.payload.into(),
+                hash: I::decode(&record.tag),
+                prev: record.prev,
+                readers: AtomicUsize::new(readers),
+            };
+            bytes += message_bytes::<I>(&message);
+            *slots[(record.offset % size as u64) as usize].write().unwrap() = Some(message);
+        }
+        // a record missing from the middle of the log (the files were tampered
+        // with) becomes an empty message that fails verification, so readers
+        // see the gap and it does not hold up the head forever
+        for offset in recovered.head..recovered.tail {
+            let mut slot = slots[(offset % size as u64) as usize].write().unwrap();
+            if slot.is_none() {
+                let message = Message {
+                    offset,
+                    bytes: Arc::default(),
+                    hash: None,
+                    prev: Vec::new(),
+                    readers: AtomicUsize::new(0),
+                };
+                bytes += message_bytes::<I>(&message);
+                *slot = Some(message);
+            }
+        }
+
+        let readers: Box<[ReaderSlot]> = (0..num_readers)
+            .map(|_| ReaderSlot {
+                members: AtomicUsize::new(0),
+                group: AtomicBool::new(false),
+                offset: AtomicU64::new(recovered.head),
+                name: OnceLock::new(),
+                state: Mutex::default(),
+            })
+            .collect();
+        for ((name, offset), slot) in recovered.cursors.into_iter().zip(readers.iter()) {
+            slot.offset.store(offset, Release);
+            let _ = slot.name.set(name);
+        }
+
+        Self {
+            shared: Arc::new(Shared {
+                log: NEXT_LOG.fetch_add(1, Relaxed),
+                num_readers,
+                readers,
+                naming: Mutex::new(()),
+                writer_taken: AtomicBool::new(false),
+                multi_producer,
+                sequencer: Mutex::new(()),
+                closed: AtomicBool::new(false),
+                slots,
+                head: AtomicU64::new(recovered.head),
+                tail: AtomicU64::new(recovered.tail),
+                published: Notify::new(),
+                freed: Notify::new(),
+                size,
+                max_bytes,
+                bytes: AtomicUsize::new(bytes),
+                backpressure,
+                dropped: AtomicU64::new(0),
+                integrity,
+                chained,
+                visibility_timeout,
+                last_hash: Mutex::new(recovered.last_tag),
+                encryption,
+                audit: audit.map(Mutex::new),
+                storage,
+                observer,
+                counters: Counters::default(),
+                parent,
+                name,
+                topics,
+            }),
+        }
+    }
+
+    /// Flushes the segment files of a persistent logger to the disk.
+    pub fn sync(&self) -> Result<(), LoggerError> {
+        if let Some(storage) = &self.shared.storage {
+            storage.sync()?;
+        }
         Ok(())
     }
 
-    /// Error Code 2 means there are too many readers (ie. greater than the quorum)
-    fn read(&mut self) -> Result<Option<Response>, usize> {
-        let thread_id = thread::current().id();
-        // eprintln!("thread {:?} is reading...", thread_id);
-        // use a read lock here to allow other threads check this condition
-        let readers = self.readers.read().unwrap();
-        // if there are more readers than necessary, then return early as only 3 readers are needed for our quorum.
-        if readers.len() > self.num_readers {
-            eprintln!("reader: too many readers. dropping thread {:?}", thread_id);
-            return Err(2);
+    /// Returns the writer for this logger, or [`LoggerError::WriterTaken`] if
+    /// one is already in use. The writer is given back when it is dropped.
+    /// A logger built with [`Builder::multi_producer`] hands out a new writer
+    /// every time.
+    pub fn writer(&self) -> Result<Writer<I>, LoggerError> {
+        if !self.shared.multi_producer && self.shared.writer_taken.swap(true, AcqRel) {
+            return Err(LoggerError::WriterTaken);
+        }
+        Ok(Writer { shared: self.shared.clone() })
+    }
+
+    /// Registers a reader in one of the `num_readers` slots of the quorum.
+    /// A slot that was given up by an earlier reader is reused, and the new
+    /// reader starts where that one stopped.
+    pub fn subscribe(&self) -> Result<Reader<I>, LoggerError> {
+        for (id, slot) in self.shared.readers.iter().enumerate() {
+            if slot.name.get().is_none()
+                && slot
+                    .members
+                    .compare_exchange(0, 1, AcqRel, Acquire)
+                    .is_ok()
+            {
+                // a durable reader may have claimed the slot in the meantime
+                if slot.name.get().is_some() {
+                    slot.members.fetch_sub(1, AcqRel);
+                    continue;
+                }
+                return Ok(Reader::new(self.shared.clone(), id));
+            }
         }
+        Err(self.shared.reject(LoggerError::ReaderRejected { num_readers: self.shared.num_readers }))
+    }
+
+    /// Subscribes as the reader called `name`. The slot taken by a durable
+    /// reader stays reserved for that name, and on a persistent logger its
+    /// offset is checkpointed on every read, so after a restart the reader
+    /// carries on right after the last message it read.
+    pub fn subscribe_durable(&self, name: &str) -> Result<Reader<I>, LoggerError> {
+        self.subscribe_named(name, false)
+    }
+
+    /// Joins the consumer group `name`. A group takes a single slot of the
+    /// quorum, however many members it has: every message is handed to one
+    /// member only, and counts as read by the group once that member has
+    /// read it. Members take the next message whenever they read, so work
+    /// is shared out again as soon as a member joins or leaves.
+    ///
+    /// Like a durable reader's, the group's slot stays reserved for it, and
+    /// on a persistent logger its offset is checkpointed on every read.
+    pub fn subscribe_group(&self, name: &str) -> Result<Reader<I>, LoggerError> {
+        self.subscribe_named(name, true)
+    }
+
+    fn subscribe_named(&self, name: &str, group: bool) -> Result<Reader<I>, LoggerError> {
+        let shared = &*self.shared;
+        let _naming = shared.naming.lock().unwrap();
 
-        let thread_count = match readers.get(&thread_id) {
-            Some(&i) => i,
-            None => 0,
+        let (id, claimed) = match shared.readers.iter().position(|slot| slot.name.get().is_some_and(|n| n == name)) {
+            Some(id) => {
+                let slot = &shared.readers[id];
+                // a group can only be joined while it is a group, and a
+                // durable reader only subscribes once
+                let joined = if group && slot.group.load(Acquire) {
+                    slot.members.fetch_add(1, AcqRel);
+                    true
+                } else {
+                    slot.members.compare_exchange(0, 1, AcqRel, Acquire).is_ok()
+                };
+                if !joined {
+                    return Err(shared.reject(LoggerError::ReaderInUse { name: name.to_string() }));
+                }
+                (id, false)
+            }
+            None => {
+                let id = shared.readers.iter().position(|slot| {
+                    slot.name.get().is_none() && slot.members.compare_exchange(0, 1, AcqRel, Acquire).is_ok()
+                });
+                match id {
+                    Some(id) => (id, true),
+                    None => {
+                        return Err(shared.reject(LoggerError::ReaderRejected { num_readers: shared.num_readers }))
+                    }
+                }
+            }
         };
 
-        drop(readers);
+        // dropping the reader hands the slot back if the checkpoint can't be
+        // set up, and a slot claimed just now is only named once it is
+        let reader = Reader::new(self.shared.clone(), id);
+        let slot = reader.slot();
+        if let Some(storage) = &shared.storage {
+            let mut state = slot.state.lock().unwrap();
+            if state.cursor.is_none() {
+                state.cursor = Some(storage.cursor(name, slot.offset.load(Acquire))?);
+            }
+        }
+        if claimed {
+            let _ = slot.name.set(name.to_string());
+        }
+        slot.group.store(group, Release);
+        Ok(reader)
+    }
+
+    /// Stops the logger, and every topic created on it, from accepting new
+    /// messages. Readers can still read what was written before, after which
+    /// they get [`LoggerError::Closed`].
+    pub fn close(&self) {
+        self.shared.closed.store(true, Release);
+        self.shared.published.notify();
+        self.shared.freed.notify();
+        if let Some(topics) = &self.shared.topics {
+            for topic in topics.loggers.lock().unwrap().values() {
+                topic.close();
+            }
+        }
+    }
+
+    // The logger this one is a topic of, itself if it is not a topic, or
+    // `None` if that logger is gone.
+    fn root(&self) -> Option<Logger<I>> {
+        match &self.shared.parent {
+            Some(parent) => parent.upgrade().map(|shared| Logger { shared }),
+            None => Some(self.clone()),
+        }
+    }
+
+    /// Creates the topic `name`: a log of its own, with room for `size`
+    /// messages that are dropped once `num_readers` readers have read them.
+    /// It is configured like this logger otherwise, and shares its keys and
+    /// observer. An encrypted topic uses a key derived from the logger's
+    /// encryption key and `name`. The returned handle works like any other
+    /// logger.
+    ///
+    /// A persistent logger keeps each topic in a directory of its own, so
+    /// creating the topic again after a restart picks up where it left off.
+    /// Topics can be created through a topic's handle too, they all belong to
+    /// the same logger. Fails with [`LoggerError::Closed`] once that logger is
+    /// closed or dropped.
+    pub fn create_topic(&self, name: &str, num_readers: usize, size: usize) -> Result<Logger<I>, LoggerError> {
+        let root = self.root().ok_or(LoggerError::Closed)?;
+        let shared = &*root.shared;
+        let topics = shared.topics.as_ref().ok_or(LoggerError::Closed)?;
+        let mut loggers = topics.loggers.lock().unwrap();
+        if shared.closed.load(Acquire) {
+            return Err(LoggerError::Closed);
+        }
+        if loggers.contains_key(name) {
+            return Err(LoggerError::TopicExists { name: name.to_string() });
+        }
+        let dir = shared.storage.as_ref().map(|storage| storage.topic_dir(name));
+        let topic = topics.template.clone().topic(
+            name,
+            num_readers,
+            size,
+            shared.integrity.clone(),
+            Arc::downgrade(&root.shared),
+            dir,
+        )?;
+        loggers.insert(name.to_string(), topic.clone());
+        Ok(topic)
+    }
+
+    /// The topic `name`, if it has been created.
+    pub fn topic(&self, name: &str) -> Option<Logger<I>> {
+        let root = self.root()?;
+        let topics = root.shared.topics.as_ref()?;
+        let topic = topics.loggers.lock().unwrap().get(name).cloned();
+        topic
+    }
+
+    /// Names of the topics created so far, in order.
+    pub fn topics(&self) -> Vec<String> {
+        match self.root() {
+            Some(root) => match &root.shared.topics {
+                Some(topics) => topics.loggers.lock().unwrap().keys().cloned().collect(),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Subscribes to every topic in `names` at once, taking a reader slot in
+    /// each of them. Fails with [`LoggerError::UnknownTopic`] if one of them
+    /// has not been created, and gives back the slots already taken if any
+    /// subscription fails.
+    pub fn subscribe_topics(&self, names: &[&str]) -> Result<TopicReader<I>, LoggerError> {
+        let readers = names
+            .iter()
+            .map(|name| {
+                let topic = self.topic(name).ok_or_else(|| LoggerError::UnknownTopic { name: name.to_string() })?;
+                Ok((name.to_string(), topic.subscribe()?))
+            })
+            .collect::<Result<_, LoggerError>>()?;
+        Ok(TopicReader::new(readers))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Acquire)
+    }
+
+    /// Number of readers currently subscribed, counting every member of a
+    /// consumer group.
+    pub fn subscribers(&self) -> usize {
+        self.shared
+            .readers
+            .iter()
+            .map(|slot| slot.members.load(Acquire))
+            .sum()
+    }
+
+    /// The latest checkpoint signed every `interval` messages, see
+    /// [`Builder::audit`].
+    pub fn checkpoint(&self) -> Result<Option<Checkpoint>, LoggerError> {
+        Ok(self.audit()?.checkpoint.clone())
+    }
+
+    /// Signs a checkpoint over every message written so far.
+    pub fn sign_checkpoint(&self) -> Result<Checkpoint, LoggerError> {
+        Ok(self.audit()?.sign()?)
+    }
+
+    /// Proof that the message at `offset` is in the Merkle tree over the
+    /// first `size` messages, to be checked with [`verify_inclusion`].
+    pub fn inclusion_proof(&self, offset: u64, size: u64) -> Result<Vec<Hash>, LoggerError> {
+        let audit = self.audit()?;
+        let len = audit.tree.len();
+        if size > len {
+            return Err(LoggerError::OutOfRange { index: size, size: len });
+        }
+        if offset >= size {
+            return Err(LoggerError::OutOfRange { index: offset, size });
+        }
+        Ok(audit.tree.inclusion(offset, size)?)
+    }
+
+    /// Proof that the Merkle tree over the first `old_size` messages is a
+    /// prefix of the one over the first `new_size`, to be checked with
+    /// [`verify_consistency`].
+    pub fn consistency_proof(&self, old_size: u64, new_size: u64) -> Result<Vec<Hash>, LoggerError> {
+        let audit = self.audit()?;
+        let len = audit.tree.len();
+        if new_size > len {
+            return Err(LoggerError::OutOfRange { index: new_size, size: len });
+        }
+        if old_size > new_size {
+            return Err(LoggerError::OutOfRange { index: old_size, size: new_size });
+        }
+        Ok(audit.tree.consistency(old_size, new_size)?)
+    }
+
+    fn audit(&self) -> Result<std::sync::MutexGuard<'_, Audit>, LoggerError> {
+        match &self.shared.audit {
+            Some(audit) => Ok(audit.lock().unwrap()),
+            None => Err(LoggerError::Unaudited),
+        }
+    }
+
+    /// Number of messages thrown away because the buffer was full and the
+    /// logger uses [`Backpressure::DropNewest`].
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Acquire)
+    }
 
-        // return none if we're at the end of the buffer
-        if self.messages.is_empty() || thread_count > 0 && thread_count > self.messages.len() - 1 {
-            eprintln!("reader: at the end of the buffer {} {}", self.messages.len(), thread_count);
-            return Ok(None);
+    /// Number of messages currently retained.
+    pub fn len(&self) -> usize {
+        let head = self.shared.head.load(Acquire);
+        self.shared.tail.load(Acquire).saturating_sub(head) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.size
+    }
+
+    /// The byte budget set with [`Builder::max_bytes`], if any.
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.shared.max_bytes
+    }
+
+    /// Bytes taken up by the retained messages, counted as for
+    /// [`Builder::max_bytes`].
+    pub fn bytes(&self) -> usize {
+        self.shared.bytes.load(Acquire)
+    }
+
+    /// What a message with a payload of `len` bytes written now counts as
+    /// against the byte budget, including the tag it would be given and,
+    /// on an encrypted logger, the authentication tag added to the payload.
+    pub fn message_bytes(&self, len: usize) -> usize {
+        let shared = &*self.shared;
+        let stored = len + shared.encryption.as_ref().map_or(0, EncryptionKey::overhead);
+        let hash = shared.integrity.sign(&[]);
+        let prev = if shared.chained { I::encode(&hash).len() } else { 0 };
+        mem::size_of::<Message<I::Tag>>() + ARC_HEADER + stored + I::tag_bytes(&hash) + prev
+    }
+
+    /// Offset of the oldest message still retained.
+    pub fn first_offset(&self) -> u64 {
+        self.shared.head.load(Acquire)
+    }
+
+    /// Offset the next written message will get.
+    pub fn next_offset(&self) -> u64 {
+        self.shared.tail.load(Acquire)
+    }
+
+    /// Takes a snapshot of the buffer's occupancy, the running totals and how
+    /// far behind each reader is. Render it with [`Stats::to_prometheus`] to
+    /// expose it for scraping.
+    pub fn stats(&self) -> Stats {
+        let shared = &*self.shared;
+        let head = shared.head.load(Acquire);
+        let tail = shared.tail.load(Acquire).max(head);
+        let counters = &shared.counters;
+        Stats {
+            capacity: shared.size,
+            occupancy: (tail - head) as usize,
+            max_bytes: shared.max_bytes,
+            bytes: shared.bytes.load(Acquire),
+            head,
+            tail,
+            written: counters.written.load(Relaxed),
+            read: counters.read.load(Relaxed),
+            evicted: counters.evicted.load(Relaxed),
+            overwritten: counters.overwritten.load(Relaxed),
+            rejected: counters.rejected.load(Relaxed),
+            dropped: shared.dropped.load(Acquire),
+            readers: shared
+                .readers
+                .iter()
+                .enumerate()
+                .map(|(id, slot)| {
+                    // a slot that fell behind the head skips to it on its next read
+                    let offset = slot.offset.load(Acquire).max(head);
+                    ReaderStats {
+                        slot: id,
+                        name: slot.name.get().cloned(),
+                        active: slot.members.load(Acquire) > 0,
+                        members: slot.members.load(Acquire),
+                        offset,
+                        lag: tail.saturating_sub(offset),
+                    }
+                })
+                .collect(),
         }
+    }
+}
+
+pub struct Response<T = Signature> {
+    pub offset: u64,
+    /// The payload, shared with the logger and every other reader.
+    pub message: Arc<[u8]>,
+    /// The message's tag, as made by the logger's [`Integrity`].
+    pub hash: T,
+    /// Whether the message matches its tag. A message that fails the check
+    /// is still handed out, this is how readers find out.
+    pub is_valid: bool,
+    /// In a chained log, set when this message does not follow on from the
+    /// last one this reader got: messages in between were removed, or this
+    /// one was moved or replayed. Always false otherwise, and for a message
+    /// that is delivered again.
+    pub chain_break: bool,
+    /// The token to acknowledge the message with, on a logger built with
+    /// [`Builder::visibility_timeout`].
+    pub token: Option<DeliveryToken>,
+}
+
+// the reference counts in front of a message's shared payload
+const ARC_HEADER: usize = 2 * mem::size_of::<usize>();
+
+// What a message counts as against the byte budget: its entry in the buffer
+// and everything it keeps on the heap.
+fn message_bytes<I: Integrity>(m: &Message<I::Tag>) -> usize {
+    let tag = m.hash.as_ref().map_or(0, I::tag_bytes);
+    mem::size_of::<Message<I::Tag>>() + ARC_HEADER + m.bytes.len() + tag + m.prev.capacity()
+}
+
+// What a message's hash covers: the payload, and in a chained log also the
+// offset and the hash of the message before it.
+fn signed_bytes<'a>(chained: bool, offset: u64, prev: &[u8], data: &'a [u8]) -> Cow<'a, [u8]> {
+    if !chained {
+        return Cow::Borrowed(data);
+    }
+    let mut buf = Vec::with_capacity(8 + prev.len() + data.len());
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(prev);
+    buf.extend_from_slice(data);
+    Cow::Owned(buf)
+}
 
-        // get message before removing it
-        let m = self.messages[thread_count].clone();
+/// The producing side of a [`Logger`].
+pub struct Writer<I: Integrity = Keys> {
+    shared: Arc<Shared<I>>,
+}
 
-        let is_valid = hmac::verify(
-            &self.key,
-            m.bytes.as_slice(),
-            m.hash.as_ref(),
-        )
-        .is_ok();
+impl<I: Integrity> Writer<I> {
+    /// Returns the offset the message was written at.
+    pub fn write(&mut self, data: &[u8]) -> Result<u64, LoggerError> {
+        self.write_batch(&[data]).map(|offsets| offsets.start)
+    }
 
-        let _ = self.messages[thread_count].readers.fetch_add(1, AcqRel);
-        let current_readers = self.messages[thread_count].readers.get_mut();
+    /// Writes every message in `batch` and returns the offsets they were
+    /// written at. Readers see either none of the batch or all of it, and the
+    /// backpressure policy applies to the batch as a whole, so a batch larger
+    /// than the buffer always fails with [`LoggerError::Full`], or with
+    /// [`LoggerError::TooLarge`] if it is over the byte budget.
+    pub fn write_batch(&mut self, batch: &[&[u8]]) -> Result<Range<u64>, LoggerError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(target: "spmc_logger", "write_batch", topic = self.shared.name.as_deref(), len = batch.len()).entered();
+        let shared = &*self.shared;
+        let _turn = shared.sequencer.lock().unwrap();
+        if shared.closed.load(Acquire) {
+            return Err(LoggerError::Closed);
+        }
 
-        let mut new_thread_count = thread_count;
-        
-        if current_readers >= &mut self.num_readers {
-            eprintln!("reader: removing message {} as all readers have read it", thread_count);
-            self.messages.remove(thread_count);
-        }else {
-            new_thread_count+=1;
+        let tail = shared.tail.load(Acquire);
+        let n = batch.len() as u64;
+        if n > shared.size as u64 {
+            return Err(LoggerError::Full { capacity: shared.size, max_bytes: None });
         }
 
-        let mut readers = self.readers.write().unwrap();
-        readers.insert(thread_id, new_thread_count);
+        // the tag covers the encrypted bytes, so readers check it before
+        // decrypting
+        let payloads: Vec<Cow<[u8]>> = (tail..)
+            .zip(batch)
+            .map(|(offset, data)| match &shared.encryption {
+                Some(key) => Cow::Owned(key.seal(offset, data)),
+                None => Cow::Borrowed(*data),
+            })
+            .collect();
+
+        // held until the messages are in place, see `purge_keys`, but not
+        // while waiting for room: a purge would wait on us with the topics
+        // locked. The tags are made again afterwards, in case the key was
+        // rotated and the old one purged in the meantime.
+        let (mut last_hash, messages, mut tags, bytes) = loop {
+            let last_hash = shared.last_hash.lock().unwrap();
+            let mut messages = Vec::with_capacity(batch.len());
+            let mut tags: Vec<Vec<u8>> = Vec::with_capacity(batch.len());
+            for (offset, payload) in (tail..).zip(&payloads) {
+                let prev = match tags.last() {
+                    _ if !shared.chained => Vec::new(),
+                    Some(tag) => Vec::clone(tag),
+                    None => last_hash.clone(),
+                };
+                let hash = shared.integrity.sign(&signed_bytes(shared.chained, offset, &prev, payload));
+                tags.push(I::encode(&hash));
+                messages.push(Message {
+                    offset,
+                    readers: AtomicUsize::new(0),
+                    bytes: Arc::from(&**payload),
+                    hash: Some(hash),
+                    prev,
+                });
+            }
+            let bytes: usize = messages.iter().map(message_bytes::<I>).sum();
+            if let Some(max_bytes) = shared.max_bytes {
+                if bytes > max_bytes {
+                    return Err(shared.reject(LoggerError::TooLarge { bytes, max_bytes }));
+                }
+            }
+            if !shared.is_full(tail, n, bytes) {
+                break (last_hash, messages, tags, bytes);
+            }
+            drop(last_hash);
+            self.make_room(tail, n, bytes)?;
+        };
 
-        Ok(Some(Response {
-            message: m.bytes.to_vec(),
-            hash: m.hash,
-            is_valid,
-        }))
+        // the leaves go first and are taken back if the records can't be
+        // appended, so a failed write leaves nothing behind at these offsets
+        // and they can be handed out again
+        let mut audit = shared.audit.as_ref().map(|audit| audit.lock().unwrap());
+        let old_len = audit.as_ref().map_or(0, |audit| audit.tree.len());
+        if let Some(audit) = &mut audit {
+            audit.tree.extend(&payloads)?;
+        }
+        // persist before publishing, so a reader never sees a message that
+        // could be lost on restart
+        if let Some(storage) = &shared.storage {
+            let entries: Vec<_> = messages
+                .iter()
+                .zip(&tags)
+                .map(|(m, tag)| Entry { offset: m.offset, tag, prev: &m.prev, payload: &m.bytes })
+                .collect();
+            if let Err(e) = storage.append(&entries) {
+                if let Some(audit) = &mut audit {
+                    audit.tree.truncate(old_len);
+                }
+                return Err(e.into());
+            }
+        }
+        // the messages are in the log already, only the checkpoint is lost
+        if let Some(Err(e)) = audit.map(|mut audit| audit.written(old_len)) {
+            shared.observer.on_error(shared.name.as_deref(), &LoggerError::Io(e));
+        }
+        if let Some(tag) = tags.pop() {
+            *last_hash = tag;
+        }
+        for (offset, message) in (tail..).zip(messages) {
+            *shared.slot(offset).write().unwrap() = Some(message);
+        }
+        drop(last_hash);
+        shared.bytes.fetch_add(bytes, AcqRel);
+        shared.tail.store(tail + n, Release);
+        shared.published.notify();
+        shared.counters.written(n);
+        for (offset, data) in (tail..).zip(batch) {
+            shared.observer.on_write(shared.name.as_deref(), offset, data.len());
+        }
+        Ok(tail..tail + n)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::{sync::{Arc, Mutex}};
-    #[test]
-    fn it_works() {
-        let logger = Arc::new(Mutex::new(Logger::new(3, 100)));
-        {
-            let l = logger.clone();
-            let original_thread = thread::spawn(move || {
-                for x in 0..10 {
-                    let message = format!("Hello my name is {}", x);
-                    let mut l = l.lock().unwrap();
-                    let _ = l.write(message.as_bytes());
+impl<I: Integrity> Writer<I> {
+    // Applies the backpressure policy to a buffer without room for `n` more
+    // messages taking up `bytes`. Returns once the messages from `tail` on
+    // can be written.
+    fn make_room(&self, tail: u64, n: u64, bytes: usize) -> Result<(), LoggerError> {
+        let shared = &*self.shared;
+        let full = || shared.is_full(tail, n, bytes);
+        shared.observer.on_full(shared.name.as_deref(), shared.size);
+
+        match shared.backpressure {
+            Backpressure::Reject => Err(shared.reject(shared.full(tail, n))),
+            Backpressure::Block { timeout } => {
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                let freed = shared.freed.wait(WaitStrategy::Park, deadline, || {
+                    !full() || shared.closed.load(Acquire)
+                });
+                if shared.closed.load(Acquire) {
+                    Err(LoggerError::Closed)
+                } else if !freed {
+                    Err(shared.reject(shared.full(tail, n)))
+                } else {
+                    Ok(())
                 }
-            });
+            }
+            Backpressure::OverwriteOldest => {
+                // the readers may finish the oldest message off themselves
+                // while we are doing this, so go by the head we find
+                while full() {
+                    let head = shared.head.load(Acquire);
+                    if shared.evict(head, |_| true) {
+                        shared.head_moved();
+                    }
+                }
+                Ok(())
+            }
+            Backpressure::DropNewest => {
+                shared.dropped.fetch_add(n, AcqRel);
+                Err(shared.reject(LoggerError::Dropped))
+            }
+        }
+    }
+}
 
-            original_thread.join().unwrap();
+impl<I: Integrity> Drop for Writer<I> {
+    fn drop(&mut self) {
+        if !self.shared.multi_producer {
+            self.shared.writer_taken.store(false, Release);
         }
+    }
+}
 
-        let threads = (0..3).map(|_| {
-            let l = logger.clone();
-            thread::spawn(move || {
-                let mut l = l.lock().unwrap();
-                for _ in 0..10 {
-                     match  l.read() {
-                        Ok(Some(res)) => {
-                            let message: String = String::from_utf8(res.message).unwrap();
-                            assert!(res.is_valid);
-                            eprintln!("Receiver: {:?}", message)
-                        },
-                        Err(_e) => /* eprintln!("ERROR {:?}", e) */ (),
-                        Ok(None) => /* eprintln!("nothing is happening") */ ()
+/// A consumer of a [`Logger`]. The reader owns its position in the log, so
+/// it is not tied to the thread that created it. Dropping the reader (or
+/// calling [`Reader::unsubscribe`]) frees its slot for another subscriber.
+pub struct Reader<I: Integrity = Keys> {
+    shared: Arc<Shared<I>>,
+    // index into `Shared::readers`
+    id: usize,
+    wait: WaitStrategy,
+    // an error hit by a batch read after it had already read some messages
+    pending: Option<LoggerError>,
+}
+
+impl<I: Integrity> Reader<I> {
+    fn new(shared: Arc<Shared<I>>, id: usize) -> Self {
+        Reader { shared, id, wait: WaitStrategy::default(), pending: None }
+    }
+
+    /// Sets how [`Reader::read_blocking`] and [`Reader::read_timeout`] wait
+    /// for new messages. Readers park by default.
+    pub fn set_wait_strategy(&mut self, wait: WaitStrategy) {
+        self.wait = wait;
+    }
+
+    /// Offset of the next message this reader will read.
+    pub fn offset(&self) -> u64 {
+        self.slot().offset.load(Acquire)
+    }
+
+    /// The name this reader subscribed with, if it is durable, or its
+    /// group's name.
+    pub fn name(&self) -> Option<&str> {
+        self.slot().name.get().map(String::as_str)
+    }
+
+    /// Gives up this reader's slot. Messages it has not read yet stay in the
+    /// buffer until the next subscriber in the slot reads them.
+    pub fn unsubscribe(self) {}
+
+    fn slot(&self) -> &ReaderSlot {
+        &self.shared.readers[self.id]
+    }
+
+    /// Returns `Ok(None)` when there is nothing new to read, or
+    /// [`LoggerError::Closed`] once the logger is closed and everything has
+    /// been read. A message that fails to decrypt is skipped with
+    /// [`LoggerError::Decryption`], and acknowledged if it needs to be.
+    ///
+    /// On a logger built with [`Builder::visibility_timeout`], the message
+    /// comes with a [`Response::token`] and only counts as read once it is
+    /// acknowledged. Messages whose timeout ran out are delivered again
+    /// before new ones.
+    pub fn read(&mut self) -> Result<Option<Response<I::Tag>>, LoggerError> {
+        if let Some(err) = self.pending.take() {
+            return Err(err);
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(target: "spmc_logger", "read", topic = self.shared.name.as_deref(), reader = self.id).entered();
+        let shared = &*self.shared;
+
+        loop {
+            let redelivery = self.slot().state.lock().unwrap().in_flight.due(Instant::now());
+            let offset = match redelivery {
+                Some(offset) => offset,
+                None => {
+                    // messages before the head have already been read by the whole quorum
+                    let offset = self.offset().max(shared.head.load(Acquire));
+
+                    // check before looking at the tail, so a message written just
+                    // before the close is not missed
+                    let closed = shared.closed.load(Acquire);
+                    // return none if we're at the end of the buffer
+                    if offset >= shared.tail.load(Acquire) {
+                        // unacknowledged messages still have to be read again
+                        if closed && self.slot().state.lock().unwrap().in_flight.is_empty() {
+                            return Err(LoggerError::Closed);
+                        }
+                        return Ok(None);
                     }
+                    offset
                 }
-            })
-        });
+            };
 
-        for handle in threads {
-            handle.join().unwrap();
+            let slot = shared.slot(offset).read().unwrap();
+            let m = match &*slot {
+                Some(m) if m.offset == offset => m,
+                // evicted while we were looking at it, or overwritten before
+                // it was acknowledged
+                _ => {
+                    if redelivery.is_some() {
+                        self.slot().state.lock().unwrap().in_flight.remove(offset);
+                    }
+                    continue;
+                }
+            };
+
+            let hash = m.hash.clone();
+            let prev = m.prev.clone();
+            let mut response = Response {
+                offset,
+                message: m.bytes.clone(),
+                hash: hash.clone().unwrap_or_default(),
+                is_valid: false,
+                chain_break: false,
+                token: None,
+            };
+            let mut state = self.slot().state.lock().unwrap();
+            let mut current_readers = 0;
+            if let Some(timeout) = shared.visibility_timeout {
+                let now = Instant::now();
+                if redelivery.is_some() {
+                    // acknowledged or delivered again by another member of
+                    // the group in the meantime
+                    if state.in_flight.due(now) != Some(offset) {
+                        continue;
+                    }
+                } else {
+                    if self.offset() > offset {
+                        continue;
+                    }
+                    // restart from the oldest message that is not acknowledged
+                    let checkpoint = state.in_flight.oldest().unwrap_or(offset);
+                    if let Some(cursor) = state.cursor.as_mut() {
+                        cursor.store(checkpoint)?;
+                    }
+                    self.slot().offset.store(offset + 1, Release);
+                }
+                state.in_flight.insert(offset, now + timeout);
+                response.token = Some(DeliveryToken { log: shared.log, slot: self.id, offset });
+            } else {
+                // another member of the group got to it first
+                if self.offset() > offset {
+                    continue;
+                }
+                // checkpoint before the read is counted, so the message can't be
+                // evicted while a restart would still need it for this reader
+                if let Some(cursor) = state.cursor.as_mut() {
+                    cursor.store(offset + 1)?;
+                }
+                // count the read while holding the slot so it can't be replaced under us
+                current_readers = m.readers.fetch_add(1, AcqRel) + 1;
+                self.slot().offset.store(offset + 1, Release);
+            }
+            if shared.chained && redelivery.is_none() {
+                response.chain_break = state.chain_break(offset, &prev, I::encode(&response.hash));
+            }
+            drop(state);
+            drop(slot);
+
+            shared.counters.read(1);
+            shared.observer.on_read(shared.name.as_deref(), self.id, offset);
+
+            // verify outside the slot lock, `purge_keys` locks the slots while
+            // holding the keys
+            let signed = signed_bytes(shared.chained, offset, &prev, &response.message);
+            response.is_valid = hash.is_some_and(|hash| shared.integrity.verify(&signed, &hash));
+
+            if current_readers >= shared.num_readers {
+                shared.advance_head();
+            }
+
+            if let Some(key) = &shared.encryption {
+                match key.open(offset, &response.message) {
+                    Some(message) => response.message = message.into(),
+                    None => {
+                        // it never will, so don't wait for an ack that can't come
+                        if let Some(token) = response.token {
+                            match self.ack(token) {
+                                Ok(()) | Err(LoggerError::UnknownDelivery { .. }) => (),
+                                Err(err) => return Err(err),
+                            }
+                        }
+                        return Err(LoggerError::Decryption { offset });
+                    }
+                }
+            }
+            return Ok(Some(response));
+        }
+    }
+
+    /// Acknowledges a message delivered to this reader, or to another member
+    /// of its group, so that it counts as read. Fails with
+    /// [`LoggerError::UnknownDelivery`] if it was already acknowledged.
+    pub fn ack(&self, token: DeliveryToken) -> Result<(), LoggerError> {
+        let shared = &*self.shared;
+        if !self.issued(token) {
+            return Err(LoggerError::UnknownDelivery { offset: token.offset });
+        }
+        let slot = shared.slot(token.offset).read().unwrap();
+        let mut state = self.slot().state.lock().unwrap();
+        let due = state
+            .in_flight
+            .remove(token.offset)
+            .ok_or(LoggerError::UnknownDelivery { offset: token.offset })?;
+        let checkpoint = state.in_flight.oldest().unwrap_or_else(|| self.offset());
+        if let Some(Err(e)) = state.cursor.as_mut().map(|cursor| cursor.store(checkpoint)) {
+            state.in_flight.insert(token.offset, due);
+            return Err(e.into());
+        }
+        // nothing to count if it was overwritten in the meantime
+        let current_readers = match &*slot {
+            Some(m) if m.offset == token.offset => m.readers.fetch_add(1, AcqRel) + 1,
+            _ => 0,
+        };
+        drop(state);
+        drop(slot);
+
+        if current_readers >= shared.num_readers {
+            shared.advance_head();
+        }
+        // the rest of a group may be waiting on the last ack to see `Closed`
+        if shared.closed.load(Acquire) {
+            shared.published.notify();
+        }
+        Ok(())
+    }
+
+    /// Gives a message back without acknowledging it, so it is delivered
+    /// again straight away.
+    pub fn nack(&self, token: DeliveryToken) -> Result<(), LoggerError> {
+        let mut state = self.slot().state.lock().unwrap();
+        if !self.issued(token) || !state.in_flight.contains(token.offset) {
+            return Err(LoggerError::UnknownDelivery { offset: token.offset });
+        }
+        state.in_flight.insert(token.offset, Instant::now());
+        drop(state);
+        self.shared.published.notify();
+        Ok(())
+    }
+
+    // Whether `token` was handed out in this reader's slot of this log.
+    fn issued(&self, token: DeliveryToken) -> bool {
+        token.log == self.shared.log && token.slot == self.id
+    }
+}
+
+impl<I: Integrity> Reader<I> {
+    /// Reads up to `max` messages that are already available. Returns an
+    /// empty batch when there is nothing new, like [`Reader::read`] returns
+    /// `Ok(None)`.
+    ///
+    /// The whole run of messages is claimed at once, so the reader's
+    /// position is moved and checkpointed once per batch rather than once per
+    /// message. On a logger built with [`Builder::visibility_timeout`] every
+    /// message is delivered and tracked on its own, so this is no cheaper
+    /// than calling [`Reader::read`] in a loop.
+    ///
+    /// An error hit after some messages were read is returned by the next
+    /// read instead, so those messages are not lost.
+    pub fn read_batch(&mut self, max: usize) -> Result<Vec<Response<I::Tag>>, LoggerError> {
+        let mut batch = Vec::new();
+        self.read_into(&mut batch, max)?;
+        Ok(batch)
+    }
+
+    /// Appends every message that is already available to `out` and returns
+    /// how many were added. Messages are claimed and errors are handled as in
+    /// [`Reader::read_batch`].
+    pub fn drain_into(&mut self, out: &mut Vec<Response<I::Tag>>) -> Result<usize, LoggerError> {
+        self.read_into(out, usize::MAX)
+    }
+
+    fn read_into(&mut self, out: &mut Vec<Response<I::Tag>>, max: usize) -> Result<usize, LoggerError> {
+        if self.shared.visibility_timeout.is_some() {
+            return self.read_each(out, max);
         }
+        if let Some(err) = self.pending.take() {
+            return Err(err);
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(target: "spmc_logger", "read_batch", topic = self.shared.name.as_deref(), reader = self.id, max).entered();
+        let shared = &*self.shared;
 
-        let l = logger.lock().unwrap();
+        loop {
+            let start = self.offset().max(shared.head.load(Acquire));
+            let closed = shared.closed.load(Acquire);
+            let tail = shared.tail.load(Acquire);
+            if start >= tail {
+                return if closed { Err(LoggerError::Closed) } else { Ok(0) };
+            }
+            let end = tail.min(start.saturating_add(max as u64));
+            if start >= end {
+                return Ok(0);
+            }
 
-        assert_eq!(l.messages.len(), 0);
+            // held in offset order until the reads are counted, and always
+            // taken before the slot's state as in `read`
+            let mut guards = Vec::with_capacity((end - start) as usize);
+            for offset in start..end {
+                let guard = shared.slot(offset).read().unwrap();
+                if guard.as_ref().is_none_or(|m| m.offset != offset) {
+                    break;
+                }
+                guards.push(guard);
+            }
+            // evicted while we were looking at it, go by the new head
+            if guards.is_empty() {
+                continue;
+            }
+
+            // decrypt before claiming, so the run can stop at a message that
+            // fails to and that message is skipped like `read` skips it
+            let mut messages = Vec::with_capacity(guards.len());
+            let mut failed = None;
+            for (offset, guard) in (start..).zip(&guards) {
+                let m = guard.as_ref().unwrap();
+                let message = match &shared.encryption {
+                    Some(key) => match key.open(offset, &m.bytes) {
+                        Some(message) => message.into(),
+                        None => {
+                            failed = Some(offset);
+                            break;
+                        }
+                    },
+                    None => m.bytes.clone(),
+                };
+                messages.push(message);
+            }
+            let claimed = start + messages.len() as u64 + failed.is_some() as u64;
+
+            let mut state = self.slot().state.lock().unwrap();
+            // other members of the group may have got to some of them first
+            let from = self.offset().max(start);
+            if from >= claimed {
+                continue;
+            }
+            if let Some(cursor) = state.cursor.as_mut() {
+                cursor.store(claimed)?;
+            }
+            let mut quorum = false;
+            let mut batch = Vec::with_capacity((claimed - from) as usize);
+            for (offset, guard) in (from..claimed).zip(&guards[(from - start) as usize..]) {
+                let m = guard.as_ref().unwrap();
+                quorum |= m.readers.fetch_add(1, AcqRel) + 1 >= shared.num_readers;
+                let chain_break = shared.chained
+                    && state.chain_break(offset, &m.prev, I::encode(&m.hash.clone().unwrap_or_default()));
+                batch.push((offset, m.bytes.clone(), m.hash.clone(), m.prev.clone(), chain_break));
+            }
+            self.slot().offset.store(claimed, Release);
+            drop(state);
+            drop(guards);
+
+            shared.counters.read(batch.len() as u64);
+            let count = out.len();
+            // verify outside the slot locks, see `read`
+            let messages = messages.into_iter().skip((from - start) as usize);
+            for ((offset, sealed, hash, prev, chain_break), message) in batch.into_iter().zip(messages) {
+                shared.observer.on_read(shared.name.as_deref(), self.id, offset);
+                let signed = signed_bytes(shared.chained, offset, &prev, &sealed);
+                out.push(Response {
+                    offset,
+                    message,
+                    is_valid: hash.as_ref().is_some_and(|hash| shared.integrity.verify(&signed, hash)),
+                    hash: hash.unwrap_or_default(),
+                    chain_break,
+                    token: None,
+                });
+            }
+            let count = out.len() - count;
+            if let Some(offset) = failed.filter(|offset| *offset >= from) {
+                shared.observer.on_read(shared.name.as_deref(), self.id, offset);
+                let err = LoggerError::Decryption { offset };
+                if count == 0 {
+                    if quorum {
+                        shared.advance_head();
+                    }
+                    return Err(err);
+                }
+                self.pending = Some(err);
+            }
+            if quorum {
+                shared.advance_head();
+            }
+            return Ok(count);
+        }
+    }
+
+    // Reads the messages one by one, as a logger with acknowledgements has
+    // to.
+    fn read_each(&mut self, out: &mut Vec<Response<I::Tag>>, max: usize) -> Result<usize, LoggerError> {
+        let mut count = 0;
+        let mut offsets = HashSet::new();
+        while count < max {
+            match self.read() {
+                Ok(Some(response)) => {
+                    // its timeout ran out before the batch was done, and the
+                    // caller already holds its token
+                    if !offsets.insert(response.offset) {
+                        break;
+                    }
+                    out.push(response);
+                }
+                Ok(None) => break,
+                Err(err) if count == 0 => return Err(err),
+                Err(err) => {
+                    // the reader stays closed, so there's nothing to keep
+                    if !matches!(err, LoggerError::Closed) {
+                        self.pending = Some(err);
+                    }
+                    break;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Waits for the next message. Returns [`LoggerError::Closed`] once the
+    /// logger is closed and everything has been read.
+    pub fn read_blocking(&mut self) -> Result<Response<I::Tag>, LoggerError> {
+        loop {
+            if let Some(response) = self.read_until(None)? {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Like [`Reader::read_blocking`], but gives up and returns `Ok(None)`
+    /// if nothing is written within `timeout`.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<Option<Response<I::Tag>>, LoggerError> {
+        self.read_until(Some(Instant::now() + timeout))
+    }
+
+    fn read_until(&mut self, deadline: Option<Instant>) -> Result<Option<Response<I::Tag>>, LoggerError> {
+        loop {
+            if let Some(response) = self.read()? {
+                return Ok(Some(response));
+            }
+
+            let shared = &*self.shared;
+            let offset = self.offset().max(shared.head.load(Acquire));
+            // wake up for the next unacknowledged message to come due too
+            let next_due = self.slot().state.lock().unwrap().in_flight.next_due();
+            let wake = match (deadline, next_due) {
+                (Some(deadline), Some(due)) => Some(deadline.min(due)),
+                (deadline, due) => deadline.or(due),
+            };
+            shared.published.wait(self.wait, wake, || {
+                let state = self.slot().state.lock().unwrap();
+                shared.tail.load(Acquire) > offset
+                    // a closed logger still has its unacknowledged messages
+                    // to deliver again, which only `next_due` wakes us for
+                    || (shared.closed.load(Acquire) && state.in_flight.is_empty())
+                    || state.in_flight.due(Instant::now()).is_some()
+            });
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl<I: Integrity> Drop for Reader<I> {
+    fn drop(&mut self) {
+        self.slot().members.fetch_sub(1, AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::hmac;
+    use std::fs;
+    use storage::tests::TempDir;
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn it_works() {
+        let logger = Logger::new(3, 100).unwrap();
+        let readers: Vec<_> = (0..3).map(|_| logger.subscribe().unwrap()).collect();
+        {
+            let mut w = logger.writer().unwrap();
+            let original_thread = thread::spawn(move || {
+                for x in 0..10 {
+                    let message = format!("Hello my name is {}", x);
+                    let _ = w.write(message.as_bytes());
+                }
+            });
+
+            original_thread.join().unwrap();
+        }
+
+        let threads: Vec<_> = readers.into_iter().map(|mut r| {
+            thread::spawn(move || {
+                let mut received = 0;
+                while received < 10 {
+                     match  r.read() {
+                        Ok(Some(res)) => {
+                            let message: String = String::from_utf8(res.message.to_vec()).unwrap();
+                            assert!(res.is_valid);
+                            eprintln!("Receiver: {:?}", message);
+                            received += 1;
+                        },
+                        Err(_e) => /* eprintln!("ERROR {:?}", e) */ (),
+                        Ok(None) => thread::yield_now(),
+                    }
+                }
+            })
+        }).collect();
+
+        for handle in threads {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(logger.len(), 0);
+    }
+
+    #[test]
+    fn writer_and_readers_run_concurrently() {
+        let logger = Logger::new(2, 4).unwrap();
+
+        let readers: Vec<_> = (0..2).map(|_| {
+            let mut r = logger.subscribe().unwrap();
+            thread::spawn(move || {
+                let mut expected = 0;
+                while expected < 50 {
+                    if let Ok(Some(res)) = r.read() {
+                        assert!(res.is_valid);
+                        assert_eq!(&*res.message, format!("{}", expected).into_bytes());
+                        expected += 1;
+                    }
+                }
+            })
+        }).collect();
+
+        // the buffer only holds 4 messages, so the writer has to wait for the
+        // readers to make room while they are running
+        let mut w = logger.writer().unwrap();
+        for x in 0..50 {
+            while w.write(format!("{}", x).as_bytes()).is_err() {
+                thread::yield_now();
+            }
+        }
+
+        for handle in readers {
+            handle.join().unwrap();
+        }
+
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn eviction_does_not_move_other_readers() {
+        let logger = Logger::new(2, 10).unwrap();
+        let mut w = logger.writer().unwrap();
+        for x in 0..6 {
+            assert_eq!(w.write(format!("{}", x).as_bytes()).unwrap(), x);
+        }
+
+        // the slow reader only gets through the first two messages
+        let mut slow = logger.subscribe().unwrap();
+        for x in 0..2 {
+            assert_eq!(slow.read().ok().flatten().unwrap().offset, x);
+        }
+
+        // the fast reader completes the quorum for those two, evicting them
+        let mut fast = logger.subscribe().unwrap();
+        for x in 0..6 {
+            let res = fast.read().ok().flatten().unwrap();
+            assert_eq!(res.offset, x);
+            assert_eq!(&*res.message, format!("{}", x).into_bytes());
+        }
+        assert_eq!(logger.first_offset(), 2);
+
+        // the slow reader carries on exactly where it left off
+        for x in 2..6 {
+            let res = slow.read().ok().flatten().unwrap();
+            assert_eq!(res.offset, x);
+            assert_eq!(&*res.message, format!("{}", x).into_bytes());
+        }
+        assert!(matches!(slow.read(), Ok(None)));
+        assert!(logger.is_empty());
+        assert_eq!(logger.next_offset(), 6);
+    }
+
+    #[test]
+    fn readers_move_between_threads() {
+        let logger = Logger::new(2, 10).unwrap();
+        let mut w = logger.writer().unwrap();
+        for x in 0..4 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+
+        // one thread can drive two readers
+        let mut a = logger.subscribe().unwrap();
+        let mut b = logger.subscribe().unwrap();
+        assert_eq!(a.read().ok().flatten().unwrap().offset, 0);
+        assert_eq!(b.read().ok().flatten().unwrap().offset, 0);
+
+        // and a reader keeps its position when it is handed to another thread
+        let (a, mut b) = thread::spawn(move || {
+            assert_eq!(a.read().ok().flatten().unwrap().offset, 1);
+            (a, b)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(a.offset(), 2);
+        assert_eq!(b.read().ok().flatten().unwrap().offset, 1);
+        assert_eq!(logger.first_offset(), 2);
+    }
+
+    #[test]
+    fn subscribe_refuses_readers_beyond_the_quorum() {
+        let logger = Logger::new(3, 10).unwrap();
+        let readers: Vec<_> = (0..3).map(|_| logger.subscribe().unwrap()).collect();
+        assert!(matches!(
+            logger.subscribe(),
+            Err(LoggerError::ReaderRejected { num_readers: 3 })
+        ));
+        assert_eq!(logger.subscribers(), 3);
+        drop(readers);
+        assert_eq!(logger.subscribers(), 0);
+    }
+
+    #[test]
+    fn unsubscribed_slot_is_reused_from_the_same_offset() {
+        let logger = Logger::new(2, 10).unwrap();
+        let mut w = logger.writer().unwrap();
+        for x in 0..4 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+
+        let mut a = logger.subscribe().unwrap();
+        let mut b = logger.subscribe().unwrap();
+        for _ in 0..4 {
+            b.read().unwrap().unwrap();
+        }
+        a.read().unwrap().unwrap();
+        a.unsubscribe();
+
+        // the replacement picks up the rest of what `a` had not read, so every
+        // message is still read twice before it is dropped
+        let mut c = logger.subscribe().unwrap();
+        assert_eq!(c.offset(), 1);
+        for x in 1..4 {
+            assert_eq!(c.read().unwrap().unwrap().offset, x);
+        }
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn concurrent_subscribers_never_exceed_the_quorum() {
+        let logger = Logger::new(3, 10).unwrap();
+        let barrier = Arc::new(Barrier::new(16));
+        let handles: Vec<_> = (0..16).map(|_| {
+            let l = logger.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                l.subscribe().ok()
+            })
+        }).collect();
+
+        let admitted: Vec<_> = handles
+            .into_iter()
+            .filter_map(|h| h.join().unwrap())
+            .collect();
+        assert_eq!(admitted.len(), 3);
+
+        let mut ids: Vec<_> = admitted.iter().map(|r| r.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe_race() {
+        let logger = Logger::new(3, 10).unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8).map(|_| {
+            let l = logger.clone();
+            let active = active.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    if let Ok(r) = l.subscribe() {
+                        assert!(active.fetch_add(1, SeqCst) < 3);
+                        thread::yield_now();
+                        active.fetch_sub(1, SeqCst);
+                        r.unsubscribe();
+                    }
+                }
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(logger.subscribers(), 0);
+    }
+
+    #[test]
+    fn only_one_writer_at_a_time() {
+        let logger = Logger::new(1, 10).unwrap();
+        let w = logger.writer().unwrap();
+        assert!(matches!(logger.writer(), Err(LoggerError::WriterTaken)));
+        drop(w);
+        assert!(logger.writer().is_ok());
+    }
+
+    #[test]
+    fn full_and_closed_errors() {
+        let logger = Logger::new(1, 2).unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write(b"a").unwrap();
+        w.write(b"b").unwrap();
+        assert!(matches!(w.write(b"c"), Err(LoggerError::Full { capacity: 2, max_bytes: None })));
+
+        logger.close();
+        assert!(matches!(w.write(b"c"), Err(LoggerError::Closed)));
+        // what was written before the close can still be read
+        assert_eq!(&*r.read().unwrap().unwrap().message, b"a");
+        assert_eq!(&*r.read().unwrap().unwrap().message, b"b");
+        assert!(matches!(r.read(), Err(LoggerError::Closed)));
+        assert_eq!(LoggerError::Closed.to_string(), "the logger is closed");
+    }
+
+    #[test]
+    fn unread_messages_survive_a_restart() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::open(&dir.0, 2, 10).unwrap();
+            let mut w = logger.writer().unwrap();
+            for x in 0..5 {
+                w.write(format!("{}", x).as_bytes()).unwrap();
+            }
+            let mut a = logger.subscribe().unwrap();
+            let mut b = logger.subscribe().unwrap();
+            for _ in 0..3 {
+                a.read().unwrap().unwrap();
+            }
+            // messages 0 and 1 reach the quorum
+            for _ in 0..2 {
+                b.read().unwrap().unwrap();
+            }
+            assert_eq!(logger.first_offset(), 2);
+        }
+
+        let logger = Logger::open(&dir.0, 2, 10).unwrap();
+        assert_eq!((logger.first_offset(), logger.next_offset()), (2, 5));
+        let mut r = logger.subscribe().unwrap();
+        for x in 2..5 {
+            let res = r.read().unwrap().unwrap();
+            assert_eq!(res.offset, x);
+            assert_eq!(&*res.message, format!("{}", x).into_bytes());
+            assert!(res.is_valid);
+        }
+
+        // and writing carries on from the recovered offset
+        assert_eq!(logger.writer().unwrap().write(b"5").unwrap(), 5);
+    }
+
+    #[test]
+    fn durable_readers_resume_after_a_restart() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::open(&dir.0, 2, 10).unwrap();
+            let mut w = logger.writer().unwrap();
+            for x in 0..5 {
+                w.write(format!("{}", x).as_bytes()).unwrap();
+            }
+            let mut indexer = logger.subscribe_durable("indexer").unwrap();
+            assert_eq!(indexer.name(), Some("indexer"));
+            for _ in 0..4 {
+                indexer.read().unwrap().unwrap();
+            }
+            let mut plain = logger.subscribe().unwrap();
+            for _ in 0..2 {
+                plain.read().unwrap().unwrap();
+            }
+            assert_eq!(logger.first_offset(), 2);
+            assert!(matches!(
+                logger.subscribe_durable("indexer"),
+                Err(LoggerError::ReaderInUse { .. })
+            ));
+        }
+
+        let logger = Logger::open(&dir.0, 2, 10).unwrap();
+        assert_eq!(logger.first_offset(), 2);
+        // the indexer's slot is kept for it, so a plain reader gets the other one
+        let mut plain = logger.subscribe().unwrap();
+        assert!(matches!(logger.subscribe(), Err(LoggerError::ReaderRejected { .. })));
+
+        // messages 2 and 3 were read by the indexer before the restart, so the
+        // plain reader completes their quorum on its own
+        for x in 2..5 {
+            assert_eq!(plain.read().unwrap().unwrap().offset, x);
+        }
+        assert_eq!(logger.first_offset(), 4);
+
+        let mut indexer = logger.subscribe_durable("indexer").unwrap();
+        assert_eq!(indexer.offset(), 4);
+        assert_eq!(indexer.read().unwrap().unwrap().offset, 4);
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn failed_durable_subscription_leaves_the_slot_unnamed() {
+        let dir = TempDir::new();
+        let logger = Logger::open(&dir.0, 1, 10).unwrap();
+        // the checkpoint file can't be created where a directory is in the way
+        fs::create_dir_all(dir.0.join("cursors").join("696e6465786572")).unwrap();
+        assert!(matches!(logger.subscribe_durable("indexer"), Err(LoggerError::Io(_))));
+        assert_eq!(logger.stats().readers[0].name, None);
+        assert!(logger.subscribe().is_ok());
+    }
+
+    #[test]
+    fn blocking_reads_wake_up_on_write() {
+        for wait in [WaitStrategy::Spin, WaitStrategy::Yield, WaitStrategy::Park] {
+            let logger = Logger::new(1, 10).unwrap();
+            let mut r = logger.subscribe().unwrap();
+            r.set_wait_strategy(wait);
+            let reader = thread::spawn(move || {
+                let first = r.read_blocking().unwrap();
+                let second = r.read_timeout(Duration::from_secs(10)).unwrap().unwrap();
+                (first.message, second.message)
+            });
+
+            let mut w = logger.writer().unwrap();
+            thread::sleep(Duration::from_millis(20));
+            w.write(b"a").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            w.write(b"b").unwrap();
+            assert_eq!(reader.join().unwrap(), (b"a"[..].into(), b"b"[..].into()));
+        }
+    }
+
+    #[test]
+    fn read_timeout_gives_up() {
+        let logger = Logger::new(1, 10).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        let start = Instant::now();
+        assert!(r.read_timeout(Duration::from_millis(30)).unwrap().is_none());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn close_wakes_blocked_readers() {
+        let logger = Logger::new(2, 10).unwrap();
+        let readers: Vec<_> = (0..2).map(|_| {
+            let mut r = logger.subscribe().unwrap();
+            thread::spawn(move || r.read_blocking().map(|res| res.message))
+        }).collect();
+
+        let mut w = logger.writer().unwrap();
+        w.write(b"last").unwrap();
+        logger.close();
+        for handle in readers {
+            assert_eq!(&*handle.join().unwrap().unwrap(), b"last");
+        }
+
+        let mut r = logger.subscribe().unwrap();
+        assert!(matches!(r.read_blocking(), Err(LoggerError::Closed)));
+    }
+
+    // Writes `count` messages while a reader takes 5ms over each one.
+    fn write_past_slow_reader(logger: &Logger, count: u64) -> Vec<Result<u64, LoggerError>> {
+        let mut r = logger.subscribe().unwrap();
+        let reader = thread::spawn(move || {
+            let mut offsets = Vec::new();
+            while let Ok(res) = r.read_blocking() {
+                offsets.push(res.offset);
+                thread::sleep(Duration::from_millis(5));
+            }
+            offsets
+        });
+
+        let mut w = logger.writer().unwrap();
+        let results = (0..count).map(|x| w.write(format!("{}", x).as_bytes())).collect();
+        logger.close();
+        reader.join().unwrap();
+        results
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let logger = Logger::new(1, 2).unwrap();
+        let results = write_past_slow_reader(&logger, 10);
+        assert!(results.iter().any(|r| matches!(r, Err(LoggerError::Full { capacity: 2, max_bytes: None }))));
+        assert_eq!(logger.dropped(), 0);
+    }
+
+    #[test]
+    fn block_until_the_reader_catches_up() {
+        let logger = Logger::builder(1, 2)
+            .backpressure(Backpressure::Block { timeout: None })
+            .build()
+            .unwrap();
+        let results = write_past_slow_reader(&logger, 10);
+        let offsets: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(offsets, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn block_with_timeout() {
+        let logger = Logger::builder(1, 2)
+            .backpressure(Backpressure::Block { timeout: Some(Duration::from_millis(20)) })
+            .build()
+            .unwrap();
+        let _r = logger.subscribe().unwrap();
+        let mut w = logger.writer().unwrap();
+        w.write(b"a").unwrap();
+        w.write(b"b").unwrap();
+        let start = Instant::now();
+        assert!(matches!(w.write(b"c"), Err(LoggerError::Full { .. })));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn overwrite_oldest() {
+        let logger = Logger::builder(1, 2)
+            .backpressure(Backpressure::OverwriteOldest)
+            .build()
+            .unwrap();
+        let results = write_past_slow_reader(&logger, 10);
+        assert!(results.iter().all(Result::is_ok));
+
+        let logger = Logger::builder(2, 2)
+            .backpressure(Backpressure::OverwriteOldest)
+            .build()
+            .unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        for x in 0..5 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+        // the reader lost 0..3 and carries on from the oldest message left
+        assert_eq!((logger.first_offset(), logger.len()), (3, 2));
+        assert_eq!(&*r.read().unwrap().unwrap().message, b"3");
+        assert_eq!(&*r.read().unwrap().unwrap().message, b"4");
+    }
+
+    #[test]
+    fn drop_newest() {
+        let logger = Logger::builder(1, 2)
+            .backpressure(Backpressure::DropNewest)
+            .build()
+            .unwrap();
+        let results = write_past_slow_reader(&logger, 10);
+        let dropped = results.iter().filter(|r| matches!(r, Err(LoggerError::Dropped))).count();
+        assert!(dropped > 0);
+        assert_eq!(logger.dropped(), dropped as u64);
+        assert_eq!(logger.next_offset(), 10 - dropped as u64);
+    }
+
+    #[test]
+    fn batches_are_published_together() {
+        let logger = Logger::builder(1, 4).chained(true).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert_eq!(w.write_batch(&[]).unwrap(), 0..0);
+        assert_eq!(w.write_batch(&[b"a", b"b", b"c"]).unwrap(), 0..3);
+        assert_eq!(w.write(b"d").unwrap(), 3);
+
+        // the whole batch must fit, whatever the policy
+        assert!(matches!(w.write_batch(&[&b"x"[..]; 5]), Err(LoggerError::Full { capacity: 4, max_bytes: None })));
+        assert!(matches!(w.write_batch(&[&b"x"[..]; 2]), Err(LoggerError::Full { .. })));
+
+        let batch = r.read_batch(2).unwrap();
+        let messages: Vec<_> = batch.iter().map(|res| res.message.to_vec()).collect();
+        assert_eq!(messages, [b"a", b"b"]);
+        let mut rest = Vec::new();
+        assert_eq!(r.drain_into(&mut rest).unwrap(), 2);
+        assert!(batch.iter().chain(&rest).all(|res| res.is_valid && !res.chain_break));
+        assert!(r.read_batch(10).unwrap().is_empty());
+
+        assert_eq!(w.write_batch(&[b"e", b"f"]).unwrap(), 4..6);
+        logger.close();
+        assert_eq!(r.drain_into(&mut rest).unwrap(), 2);
+        assert!(matches!(r.read_batch(10), Err(LoggerError::Closed)));
+    }
+
+    #[test]
+    fn batches_make_room_for_the_whole_batch() {
+        let logger = Logger::builder(1, 4)
+            .backpressure(Backpressure::OverwriteOldest)
+            .build()
+            .unwrap();
+        let mut w = logger.writer().unwrap();
+        let _r = logger.subscribe().unwrap();
+        w.write_batch(&[b"0", b"1", b"2"]).unwrap();
+        w.write_batch(&[b"3", b"4", b"5"]).unwrap();
+        assert_eq!((logger.first_offset(), logger.len()), (2, 4));
+
+        let logger = Logger::builder(1, 4).backpressure(Backpressure::DropNewest).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let _r = logger.subscribe().unwrap();
+        w.write_batch(&[b"0", b"1", b"2"]).unwrap();
+        assert!(matches!(w.write_batch(&[b"3", b"4"]), Err(LoggerError::Dropped)));
+        assert_eq!((logger.dropped(), logger.next_offset()), (2, 3));
+    }
+
+    #[test]
+    fn batch_reads_keep_errors_for_the_next_read() {
+        let key = EncryptionKey::new(Cipher::Aes256Gcm, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+        let dir = TempDir::new();
+        {
+            let logger = Logger::builder(1, 10).encryption(key.clone()).open(&dir.0).unwrap();
+            logger.writer().unwrap().write_batch(&[b"a", b"b", b"c"]).unwrap();
+        }
+        // flip a bit in the authentication tag of the last message
+        let path = dir.0.join(format!("{:020}.log", 0));
+        let mut segment = fs::read(&path).unwrap();
+        *segment.last_mut().unwrap() ^= 1;
+        fs::write(&path, segment).unwrap();
+
+        let logger = Logger::builder(1, 10).encryption(key).open(&dir.0).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert_eq!(r.read_batch(10).unwrap().len(), 2);
+        assert!(matches!(r.read_batch(10), Err(LoggerError::Decryption { offset: 2 })));
+        assert!(r.read_batch(10).unwrap().is_empty());
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn batch_reads_claim_a_run_at_once() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::builder(2, 10).chained(true).open(&dir.0).unwrap();
+            logger.writer().unwrap().write_batch(&[b"0", b"1", b"2", b"3", b"4"]).unwrap();
+            let mut workers = logger.subscribe_group("workers").unwrap();
+            let mut other = logger.subscribe_group("workers").unwrap();
+            let batch = workers.read_batch(3).unwrap();
+            assert_eq!(batch.iter().map(|res| res.offset).collect::<Vec<_>>(), [0, 1, 2]);
+            assert!(batch.iter().all(|res| res.is_valid && !res.chain_break));
+            assert_eq!(other.read().unwrap().unwrap().offset, 3);
+        }
+
+        // the whole run was checkpointed
+        let logger = Logger::builder(2, 10).chained(true).open(&dir.0).unwrap();
+        let mut workers = logger.subscribe_group("workers").unwrap();
+        assert_eq!(workers.read_batch(10).unwrap().iter().map(|res| res.offset).collect::<Vec<_>>(), [4]);
+        let mut plain = logger.subscribe().unwrap();
+        assert_eq!(plain.read_batch(10).unwrap().len(), 5);
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn observer_sees_every_event() {
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl Events {
+            fn push(&self, topic: Option<&str>, event: String) {
+                let topic = topic.map(|topic| format!("{}: ", topic)).unwrap_or_default();
+                self.0.lock().unwrap().push(topic + &event);
+            }
+        }
+
+        impl Observer for Events {
+            fn on_write(&self, topic: Option<&str>, offset: u64, len: usize) {
+                self.push(topic, format!("write {} {}", offset, len));
+            }
+
+            fn on_read(&self, topic: Option<&str>, reader: usize, offset: u64) {
+                self.push(topic, format!("read {} {}", reader, offset));
+            }
+
+            fn on_full(&self, topic: Option<&str>, capacity: usize) {
+                self.push(topic, format!("full {}", capacity));
+            }
+
+            fn on_reject(&self, topic: Option<&str>, error: &LoggerError) {
+                self.push(topic, format!("reject {}", error));
+            }
+
+            fn on_evict(&self, topic: Option<&str>, offset: u64, unread: bool) {
+                self.push(topic, format!("evict {} {}", offset, unread));
+            }
+        }
+
+        let events = Arc::new(Events::default());
+        let logger = Logger::builder(1, 1).observer(events.clone()).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert!(logger.subscribe().is_err());
+        w.write(b"abc").unwrap();
+        assert!(w.write(b"d").is_err());
+        r.read().unwrap().unwrap();
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                "reject all 1 reader slots are in use",
+                "write 0 3",
+                "full 1",
+                "reject buffer is full (1 unread messages)",
+                "read 0 0",
+                "evict 0 false",
+            ]
+        );
+
+        let events = Arc::new(Events::default());
+        let logger = Logger::builder(1, 1)
+            .backpressure(Backpressure::OverwriteOldest)
+            .observer(events.clone())
+            .build()
+            .unwrap();
+        let mut w = logger.writer().unwrap();
+        let _r = logger.subscribe().unwrap();
+        w.write_batch(&[b"a"]).unwrap();
+        w.write_batch(&[b"bc"]).unwrap();
+        assert_eq!(*events.0.lock().unwrap(), ["write 0 1", "full 1", "evict 0 true", "write 1 2"]);
+
+        // topics report to the same observer, under their name
+        events.0.lock().unwrap().clear();
+        let audit = logger.create_topic("audit", 1, 1).unwrap();
+        audit.writer().unwrap().write(b"x").unwrap();
+        audit.subscribe().unwrap().read().unwrap().unwrap();
+        assert_eq!(*events.0.lock().unwrap(), ["audit: write 0 1", "audit: read 0 0", "audit: evict 0 false"]);
+    }
+
+    #[test]
+    fn stats_track_occupancy_and_reader_lag() {
+        let logger = Logger::builder(2, 4).backpressure(Backpressure::OverwriteOldest).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut fast = logger.subscribe().unwrap();
+        let slow = logger.subscribe().unwrap();
+        w.write_batch(&[b"0", b"1", b"2"]).unwrap();
+        fast.drain_into(&mut Vec::new()).unwrap();
+
+        let stats = logger.stats();
+        assert_eq!((stats.capacity, stats.occupancy, stats.written, stats.read), (4, 3, 3, 3));
+        let lags: Vec<_> = stats.readers.iter().map(|r| (r.offset, r.lag)).collect();
+        assert_eq!(lags, [(3, 0), (0, 3)]);
+
+        // the slow reader loses two messages
+        w.write_batch(&[b"3", b"4", b"5"]).unwrap();
+        drop(slow);
+        let stats = logger.stats();
+        assert_eq!((stats.head, stats.tail, stats.evicted, stats.overwritten), (2, 6, 2, 2));
+        assert_eq!((stats.readers[1].active, stats.readers[1].offset, stats.readers[1].lag), (false, 2, 4));
+        assert!(stats.to_prometheus().contains("spmc_logger_reader_lag{slot=\"1\"} 4\n"));
+
+        let logger = Logger::new(1, 1).unwrap();
+        let mut w = logger.writer().unwrap();
+        let _r = logger.subscribe().unwrap();
+        w.write(b"a").unwrap();
+        assert!(w.write(b"b").is_err());
+        assert_eq!(logger.stats().rejected, 1);
+    }
+
+    #[test]
+    fn writers_share_one_ordered_log() {
+        let logger = Logger::builder(2, 8)
+            .multi_producer(true)
+            .chained(true)
+            .backpressure(Backpressure::Block { timeout: None })
+            .build()
+            .unwrap();
+        let readers: Vec<_> = (0..2).map(|_| logger.subscribe().unwrap()).collect();
+        let writers: Vec<_> = (0..4u8)
+            .map(|id| {
+                let mut w = logger.writer().unwrap();
+                thread::spawn(move || {
+                    for x in 0..50u8 {
+                        w.write_batch(&[&[id, x], &[id, x]]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = readers
+            .into_iter()
+            .map(|mut r| {
+                thread::spawn(move || {
+                    let mut next = [0u8; 4];
+                    let mut last = 0;
+                    for offset in 0..400 {
+                        let res = r.read_blocking().unwrap();
+                        assert_eq!(res.offset, offset);
+                        assert!(res.is_valid && !res.chain_break);
+                        // each writer's messages keep their order, and a batch
+                        // is never split up
+                        let (id, x) = (res.message[0] as usize, res.message[1]);
+                        assert_eq!(x, next[id] / 2);
+                        if next[id] % 2 == 1 {
+                            assert_eq!(id, last);
+                        }
+                        next[id] += 1;
+                        last = id;
+                    }
+                })
+            })
+            .collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+        assert_eq!((logger.next_offset(), logger.len()), (400, 0));
+    }
+
+    #[test]
+    fn topics_have_their_own_buffers_and_share_keys() {
+        let logger = Logger::new(1, 10).unwrap();
+        let audit = logger.create_topic("audit", 2, 4).unwrap();
+        let metrics = audit.create_topic("metrics", 1, 2).unwrap();
+        assert!(matches!(logger.create_topic("audit", 1, 1), Err(LoggerError::TopicExists { .. })));
+        assert_eq!(logger.topics(), ["audit", "metrics"]);
+        assert_eq!((audit.capacity(), metrics.capacity()), (4, 2));
+        assert!(logger.topic("metrics").is_some() && logger.topic("traces").is_none());
+
+        let mut r = audit.subscribe().unwrap();
+        audit.writer().unwrap().write(b"old").unwrap();
+        logger.rotate_key().unwrap();
+        metrics.writer().unwrap().write(b"new").unwrap();
+        assert_eq!(logger.len(), 0);
+        assert_eq!(audit.key_id(), 1);
+
+        // the unread message in the topic still needs key 0
+        assert!(metrics.purge_keys().unwrap().is_empty());
+        let res = r.read().unwrap().unwrap();
+        assert_eq!((res.hash.key_id, res.is_valid), (0, true));
+        audit.subscribe().unwrap().read().unwrap().unwrap();
+        assert_eq!(logger.purge_keys().unwrap(), vec![0]);
+        let res = metrics.subscribe().unwrap().read().unwrap().unwrap();
+        assert_eq!((res.hash.key_id, res.is_valid), (1, true));
+
+        logger.close();
+        assert!(audit.is_closed() && metrics.is_closed());
+        assert!(matches!(logger.create_topic("traces", 1, 1), Err(LoggerError::Closed)));
+    }
+
+    #[test]
+    fn readers_can_follow_several_topics() {
+        let logger = Logger::new(1, 10).unwrap();
+        let a = logger.create_topic("a", 1, 10).unwrap();
+        let b = logger.create_topic("b", 1, 10).unwrap();
+        assert!(matches!(logger.subscribe_topics(&["a", "c"]), Err(LoggerError::UnknownTopic { .. })));
+        // the slot taken in `a` was given back
+        assert_eq!(a.subscribers(), 0);
+
+        let mut r = logger.subscribe_topics(&["a", "b"]).unwrap();
+        assert_eq!(r.topics().collect::<Vec<_>>(), ["a", "b"]);
+        a.writer().unwrap().write_batch(&[b"a0", b"a1"]).unwrap();
+        b.writer().unwrap().write(b"b0").unwrap();
+
+        let mut read = Vec::new();
+        while let Some(res) = r.read().unwrap() {
+            read.push((res.topic.to_string(), res.response.message.to_vec()));
+        }
+        let expected = [("a", b"a0"), ("b", b"b0"), ("a", b"a1")].map(|(t, m)| (t.to_string(), m.to_vec()));
+        assert_eq!(read, expected);
+
+        a.close();
+        assert!(r.read().unwrap().is_none());
+        b.close();
+        assert!(matches!(r.read(), Err(LoggerError::Closed)));
+    }
+
+    #[test]
+    fn topics_survive_a_restart() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::open(&dir.0, 1, 10).unwrap();
+            logger.writer().unwrap().write(b"root").unwrap();
+            let topic = logger.create_topic("events", 1, 10).unwrap();
+            topic.writer().unwrap().write(b"event").unwrap();
+        }
+
+        let logger = Logger::open(&dir.0, 1, 10).unwrap();
+        assert!(logger.topics().is_empty());
+        assert_eq!(&*logger.subscribe().unwrap().read().unwrap().unwrap().message, b"root");
+        let topic = logger.create_topic("events", 1, 10).unwrap();
+        let res = topic.subscribe().unwrap().read().unwrap().unwrap();
+        assert_eq!((&*res.message, res.is_valid), (&b"event"[..], true));
+    }
+
+    #[test]
+    fn encrypted_topics_use_keys_of_their_own() {
+        let key = EncryptionKey::new(Cipher::ChaCha20Poly1305, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+        let dir = TempDir::new();
+        let builder = || Logger::builder(1, 10).integrity(NoIntegrity).encryption(key.clone());
+        {
+            let logger = builder().open(&dir.0).unwrap();
+            logger.writer().unwrap().write(b"AAAAAAAA").unwrap();
+            for (name, message) in [("a", b"AAAAAAAA"), ("b", b"BBBBBBBB")] {
+                logger.create_topic(name, 1, 10).unwrap().writer().unwrap().write(message).unwrap();
+            }
+        }
+
+        // the same plaintext at the same offset, but under different keys
+        let sealed = |dir: &Path| {
+            let segment = fs::read(dir.join(format!("{:020}.log", 0))).unwrap();
+            segment[segment.len() - 24..].to_vec()
+        };
+        let topics = dir.0.join("topics");
+        let (root, a, b) = (sealed(&dir.0), sealed(&topics.join("61")), sealed(&topics.join("62")));
+        assert!(root != a && a != b);
+        let xor: Vec<u8> = a.iter().zip(&b).take(8).map(|(x, y)| x ^ y).collect();
+        assert_ne!(xor, [b'A' ^ b'B'; 8]);
+
+        let logger = builder().open(&dir.0).unwrap();
+        let topic = logger.create_topic("b", 1, 10).unwrap();
+        assert_eq!(&*topic.subscribe().unwrap().read().unwrap().unwrap().message, b"BBBBBBBB");
+    }
+
+    #[test]
+    fn group_members_share_the_messages() {
+        let logger = Logger::builder(2, 16)
+            .chained(true)
+            .backpressure(Backpressure::Block { timeout: None })
+            .build()
+            .unwrap();
+        let mut r = logger.subscribe().unwrap();
+        let members: Vec<_> = (0..3).map(|_| logger.subscribe_group("workers").unwrap()).collect();
+        assert_eq!(logger.subscribers(), 4);
+        assert_eq!(logger.stats().readers[1].members, 3);
+        assert!(matches!(logger.subscribe_durable("workers"), Err(LoggerError::ReaderInUse { .. })));
+        // the group only takes one slot of the quorum
+        assert!(logger.subscribe().is_err());
+
+        let writer = {
+            let mut w = logger.writer().unwrap();
+            let logger = logger.clone();
+            thread::spawn(move || {
+                for x in 0..300u32 {
+                    w.write(&x.to_le_bytes()).unwrap();
+                }
+                logger.close();
+            })
+        };
+        let members: Vec<_> = members
+            .into_iter()
+            .map(|mut m| {
+                thread::spawn(move || {
+                    let mut offsets = Vec::new();
+                    while let Ok(res) = m.read_blocking() {
+                        assert!(res.is_valid && !res.chain_break);
+                        offsets.push(res.offset);
+                    }
+                    offsets
+                })
+            })
+            .collect();
+        for offset in 0..300 {
+            assert_eq!(r.read_blocking().unwrap().offset, offset);
+        }
+        writer.join().unwrap();
+
+        let mut offsets: Vec<_> = members.into_iter().flat_map(|m| m.join().unwrap()).collect();
+        offsets.sort_unstable();
+        assert_eq!(offsets, (0..300).collect::<Vec<_>>());
+        assert_eq!(logger.len(), 0);
+    }
+
+    #[test]
+    fn group_offsets_survive_a_restart() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::open(&dir.0, 1, 10).unwrap();
+            let mut w = logger.writer().unwrap();
+            for x in 0..4 {
+                w.write(format!("{}", x).as_bytes()).unwrap();
+            }
+            let mut a = logger.subscribe_group("workers").unwrap();
+            let mut b = logger.subscribe_group("workers").unwrap();
+            assert_eq!(a.read().unwrap().unwrap().offset, 0);
+            assert_eq!(b.read().unwrap().unwrap().offset, 1);
+        }
+
+        let logger = Logger::open(&dir.0, 1, 10).unwrap();
+        let mut a = logger.subscribe_group("workers").unwrap();
+        assert_eq!(a.name(), Some("workers"));
+        assert_eq!(&*a.read().unwrap().unwrap().message, b"2");
+        // a durable reader can't take the slot while the group is in it
+        assert!(matches!(logger.subscribe_durable("workers"), Err(LoggerError::ReaderInUse { .. })));
+        drop(a);
+        let mut durable = logger.subscribe_durable("workers").unwrap();
+        assert_eq!(&*durable.read().unwrap().unwrap().message, b"3");
+        assert!(matches!(logger.subscribe_group("workers"), Err(LoggerError::ReaderInUse { .. })));
+    }
+
+    #[test]
+    fn messages_count_once_acknowledged() {
+        let logger = Logger::builder(1, 4).visibility_timeout(Duration::from_secs(60)).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write_batch(&[b"0", b"1"]).unwrap();
+
+        let first = r.read().unwrap().unwrap().token.unwrap();
+        let second = r.read().unwrap().unwrap().token.unwrap();
+        assert!(r.read().unwrap().is_none());
+        r.ack(second).unwrap();
+        // the first message holds up the head until it is acknowledged
+        assert_eq!(logger.len(), 2);
+
+        r.nack(first).unwrap();
+        let again = r.read().unwrap().unwrap();
+        assert_eq!((again.offset, &*again.message), (0, &b"0"[..]));
+        logger.close();
+        // not everything has been acknowledged yet
+        assert!(r.read().unwrap().is_none());
+        r.ack(again.token.unwrap()).unwrap();
+        assert!(matches!(r.ack(first), Err(LoggerError::UnknownDelivery { offset: 0 })));
+        assert_eq!(logger.len(), 0);
+        assert!(matches!(r.read(), Err(LoggerError::Closed)));
+    }
+
+    #[test]
+    fn tokens_only_acknowledge_in_their_own_topic() {
+        let logger = Logger::builder(1, 4).visibility_timeout(Duration::from_secs(60)).build().unwrap();
+        for name in ["a", "b"] {
+            logger.create_topic(name, 1, 4).unwrap().writer().unwrap().write(name.as_bytes()).unwrap();
+        }
+        let mut r = logger.subscribe_topics(&["a", "b"]).unwrap();
+        let a = r.read().unwrap().unwrap().response.token.unwrap();
+        let b = r.read().unwrap().unwrap().response.token.unwrap();
+        // both were read at offset 0 in slot 0, of different topics
+        assert!(matches!(r.ack("b", a), Err(LoggerError::UnknownDelivery { offset: 0 })));
+        assert!(matches!(r.nack("a", b), Err(LoggerError::UnknownDelivery { offset: 0 })));
+        assert_eq!(logger.topic("b").unwrap().len(), 1);
+
+        r.ack("a", a).unwrap();
+        r.ack("b", b).unwrap();
+        assert!(logger.topic("a").unwrap().is_empty() && logger.topic("b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_delivered_again() {
+        let logger = Logger::builder(1, 4).visibility_timeout(Duration::from_millis(50)).build().unwrap();
+        logger.writer().unwrap().write_batch(&[b"0", b"1"]).unwrap();
+
+        // a member of the group dies before acknowledging its message
+        let mut crashed = logger.subscribe_group("workers").unwrap();
+        assert_eq!(crashed.read().unwrap().unwrap().offset, 0);
+        drop(crashed);
+
+        let mut r = logger.subscribe_group("workers").unwrap();
+        let res = r.read().unwrap().unwrap();
+        assert_eq!(res.offset, 1);
+        r.ack(res.token.unwrap()).unwrap();
+        assert!(r.read().unwrap().is_none());
+
+        let start = Instant::now();
+        let res = r.read_timeout(Duration::from_secs(10)).unwrap().unwrap();
+        assert_eq!(res.offset, 0);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        r.ack(res.token.unwrap()).unwrap();
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn closed_loggers_wait_for_unacknowledged_messages_until_the_deadline() {
+        let logger = Logger::builder(1, 4).visibility_timeout(Duration::from_secs(3)).build().unwrap();
+        logger.writer().unwrap().write(b"0").unwrap();
+        let mut r = logger.subscribe().unwrap();
+        let _unacked = r.read().unwrap().unwrap();
+        logger.close();
+
+        let start = Instant::now();
+        assert!(r.read_timeout(Duration::from_millis(100)).unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn batches_hold_each_message_once() {
+        let logger = Logger::builder(1, 100).visibility_timeout(Duration::from_micros(1)).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        for i in 0..100u32 {
+            w.write(&i.to_le_bytes()).unwrap();
+        }
+        let mut r = logger.subscribe().unwrap();
+        let batch = r.read_batch(100_000).unwrap();
+        let offsets: HashSet<_> = batch.iter().map(|res| res.offset).collect();
+        assert_eq!(offsets.len(), batch.len());
+        assert!(batch.len() <= 100);
+    }
+
+    #[test]
+    fn restart_resumes_from_the_oldest_unacknowledged_message() {
+        let dir = TempDir::new();
+        let open = || Logger::builder(1, 10).visibility_timeout(Duration::from_secs(60)).open(&dir.0).unwrap();
+        {
+            let logger = open();
+            logger.writer().unwrap().write_batch(&[b"0", b"1", b"2"]).unwrap();
+            let mut r = logger.subscribe_durable("indexer").unwrap();
+            let _unacked = r.read().unwrap().unwrap();
+            let res = r.read().unwrap().unwrap();
+            r.ack(res.token.unwrap()).unwrap();
+        }
+
+        let logger = open();
+        let mut r = logger.subscribe_durable("indexer").unwrap();
+        let offsets: Vec<_> = (0..3).map(|_| r.read().unwrap().unwrap().offset).collect();
+        assert_eq!(offsets, [0, 1, 2]);
+    }
+
+    #[test]
+    fn messages_that_fail_to_decrypt_are_acknowledged() {
+        let key = EncryptionKey::new(Cipher::Aes256Gcm, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+        let dir = TempDir::new();
+        let open = || {
+            Logger::builder(1, 10)
+                .visibility_timeout(Duration::from_millis(10))
+                .encryption(key.clone())
+                .open(&dir.0)
+                .unwrap()
+        };
+        open().writer().unwrap().write(b"secret").unwrap();
+        // flip a bit in the authentication tag
+        let path = dir.0.join(format!("{:020}.log", 0));
+        let mut segment = fs::read(&path).unwrap();
+        *segment.last_mut().unwrap() ^= 1;
+        fs::write(&path, segment).unwrap();
+
+        let logger = open();
+        let mut r = logger.subscribe().unwrap();
+        assert!(matches!(r.read(), Err(LoggerError::Decryption { offset: 0 })));
+        assert!(logger.is_empty());
+        thread::sleep(Duration::from_millis(20));
+        assert!(r.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn byte_budget_limits_the_buffer() {
+        let logger = Logger::new(1, 1).unwrap();
+        // the entry, the payload behind its reference counts, and the HMAC tag
+        let fixed = mem::size_of::<Message<Signature>>() + ARC_HEADER;
+        assert_eq!(logger.message_bytes(10), fixed + 10 + 32);
+        // a chained message also keeps the encoded tag before it
+        let chained = Logger::builder(1, 1).chained(true).build().unwrap();
+        assert_eq!(chained.message_bytes(10), fixed + 10 + 32 + 36);
+        let budget = logger.message_bytes(10) * 3;
+        let logger = Logger::builder(1, 100).max_bytes(budget).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert_eq!(logger.max_bytes(), Some(budget));
+
+        w.write_batch(&[&[0; 10], &[1; 10]]).unwrap();
+        assert_eq!(logger.bytes(), logger.message_bytes(10) * 2);
+        // a count of 100 leaves plenty of room, the bytes don't
+        let full = w.write(&[2; 20]).unwrap_err();
+        assert!(matches!(full, LoggerError::Full { max_bytes: Some(max_bytes), .. } if max_bytes == budget));
+        assert_eq!(full.to_string(), format!("buffer is full ({} bytes of unread messages)", budget));
+        w.write(&[2; 5]).unwrap();
+        assert!(matches!(
+            w.write(&vec![3; budget]),
+            Err(LoggerError::TooLarge { max_bytes, .. }) if max_bytes == budget
+        ));
+
+        r.read().unwrap().unwrap();
+        assert_eq!(logger.bytes(), logger.message_bytes(10) + logger.message_bytes(5));
+        w.write(&[3; 5]).unwrap();
+        let stats = logger.stats();
+        assert_eq!((stats.bytes, stats.max_bytes, stats.occupancy), (logger.bytes(), Some(budget), 3));
+    }
+
+    #[test]
+    fn byte_budget_applies_the_backpressure_policy() {
+        let key = EncryptionKey::new(Cipher::Aes256Gcm, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+        let builder = || Logger::builder(1, 100).encryption(key.clone());
+        // encrypted payloads are 16 bytes longer
+        let budget = builder().build().unwrap().message_bytes(1) * 3;
+        assert_eq!(budget, Logger::new(1, 1).unwrap().message_bytes(17) * 3);
+        let logger = builder().max_bytes(budget).backpressure(Backpressure::OverwriteOldest).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        for x in 0..5u8 {
+            w.write(&[x]).unwrap();
+        }
+        assert_eq!((logger.first_offset(), logger.len()), (2, 3));
+        assert_eq!(logger.bytes(), budget);
+        assert_eq!(&*r.read().unwrap().unwrap().message, [2]);
+    }
+
+    #[test]
+    fn tags_can_be_checked_with_a_shared_key() {
+        let logger = Logger::builder(1, 10).key_bytes(b"shared secret").build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write(b"hello").unwrap();
+
+        let res = r.read().unwrap().unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"shared secret");
+        assert!(hmac::verify(&key, &res.message, &res.hash.bytes).is_ok());
+    }
+
+    #[test]
+    fn key_file_is_shared_between_loggers() {
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("logger.key");
+
+        let first = Logger::builder(1, 10).key_file(&path).build().unwrap();
+        first.writer().unwrap().write(b"hello").unwrap();
+        let res = first.subscribe().unwrap().read().unwrap().unwrap();
+
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &load_key(&path).unwrap());
+        assert!(hmac::verify(&key, &res.message, &res.hash.bytes).is_ok());
+
+        let second = Logger::builder(1, 10).key_file(&path).build().unwrap();
+        second.writer().unwrap().write(b"hello").unwrap();
+        assert_eq!(second.subscribe().unwrap().read().unwrap().unwrap().hash, res.hash);
+    }
+
+    #[test]
+    fn rotated_keys_verify_old_messages_until_purged() {
+        let logger = Logger::new(1, 10).unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write(b"old").unwrap();
+        assert_eq!(logger.rotate_key().unwrap(), 1);
+        w.write(b"new").unwrap();
+
+        // key 0 is still needed for the first message
+        assert!(logger.purge_keys().unwrap().is_empty());
+        let old = r.read().unwrap().unwrap();
+        assert_eq!((old.hash.key_id, old.is_valid), (0, true));
+        let new = r.read().unwrap().unwrap();
+        assert_eq!((new.hash.key_id, new.is_valid), (1, true));
+
+        assert_eq!(logger.purge_keys().unwrap(), vec![0]);
+        assert_eq!(logger.key_ids(), vec![1]);
+        assert_eq!(logger.key_id(), 1);
+    }
+
+    #[test]
+    fn purges_dont_wait_on_blocked_writers() {
+        let logger = Logger::builder(1, 1).backpressure(Backpressure::Block { timeout: None }).build().unwrap();
+        let topic = logger.create_topic("a", 1, 1).unwrap();
+        let mut w = topic.writer().unwrap();
+        w.write(b"0").unwrap();
+        let writer = thread::spawn(move || w.write(b"1"));
+        thread::sleep(Duration::from_millis(20));
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let purger = logger.clone();
+        thread::spawn(move || done.send(purger.purge_keys().unwrap()).unwrap());
+        assert!(finished.recv_timeout(Duration::from_secs(5)).unwrap().is_empty());
+        logger.close();
+        assert!(matches!(writer.join().unwrap(), Err(LoggerError::Closed)));
+    }
+
+    #[test]
+    fn rotated_keys_survive_a_restart() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::open(&dir.0, 1, 10).unwrap();
+            let mut w = logger.writer().unwrap();
+            w.write(b"old").unwrap();
+            logger.rotate_key().unwrap();
+            w.write(b"new").unwrap();
+        }
+
+        let logger = Logger::open(&dir.0, 1, 10).unwrap();
+        assert_eq!(logger.key_id(), 1);
+        let mut r = logger.subscribe().unwrap();
+        for key_id in 0..2 {
+            let res = r.read().unwrap().unwrap();
+            assert_eq!((res.hash.key_id, res.is_valid), (key_id, true));
+        }
+        assert_eq!(logger.purge_keys().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn chained_messages_verify_in_order() {
+        let logger = Logger::builder(1, 10).chained(true).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        for x in 0..5 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+        for _ in 0..5 {
+            let res = r.read().unwrap().unwrap();
+            assert!(res.is_valid);
+            assert!(!res.chain_break);
+        }
+    }
+
+    #[test]
+    fn chain_break_is_reported_for_skipped_messages() {
+        let logger = Logger::builder(1, 2)
+            .chained(true)
+            .backpressure(Backpressure::OverwriteOldest)
+            .build()
+            .unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write(b"0").unwrap();
+        assert!(!r.read().unwrap().unwrap().chain_break);
+        // 1 is overwritten before the reader gets to it
+        for x in 1..4 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+        let res = r.read().unwrap().unwrap();
+        assert_eq!(res.offset, 2);
+        assert!(res.is_valid);
+        assert!(res.chain_break);
+        assert!(!r.read().unwrap().unwrap().chain_break);
+    }
+
+    #[test]
+    fn chains_carry_on_after_a_restart() {
+        let dir = TempDir::new();
+        let open = || Logger::builder(1, 10).chained(true).open(&dir.0).unwrap();
+        let first = {
+            let logger = open();
+            logger.writer().unwrap().write(b"0").unwrap();
+            // read by the whole quorum, so nothing is left to recover
+            logger.subscribe().unwrap().read().unwrap().unwrap()
+        };
+
+        let logger = open();
+        assert!(logger.is_empty());
+        logger.writer().unwrap().write(b"1").unwrap();
+        drop(logger);
+        let (_, recovered) = Storage::open(&dir.0, storage::SEGMENT_BYTES).unwrap();
+        assert_eq!(recovered.records[0].offset, 1);
+        assert_eq!(recovered.records[0].prev, Keys::encode(&first.hash));
+    }
+
+    #[test]
+    fn chain_break_is_reported_for_a_tampered_log() {
+        let dir = TempDir::new();
+        let path = dir.0.join(format!("{:020}.log", 0));
+        let mut ends = Vec::new();
+        {
+            let logger = Logger::builder(1, 10).chained(true).open(&dir.0).unwrap();
+            let mut w = logger.writer().unwrap();
+            for x in 0..3 {
+                w.write(&[x; 4]).unwrap();
+                ends.push(fs::metadata(&path).unwrap().len() as usize);
+            }
+        }
+
+        // cut the middle record out of the segment
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.drain(ends[0]..ends[1]);
+        fs::write(&path, bytes).unwrap();
+
+        let logger = Logger::builder(1, 10).chained(true).open(&dir.0).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert!(!r.read().unwrap().unwrap().chain_break);
+        let gap = r.read().unwrap().unwrap();
+        assert_eq!(gap.offset, 1);
+        assert!(!gap.is_valid);
+        assert!(gap.chain_break);
+        let res = r.read().unwrap().unwrap();
+        assert_eq!(res.offset, 2);
+        assert!(res.is_valid);
+        assert!(res.chain_break);
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn gaps_count_against_the_size_on_open() {
+        let dir = TempDir::new();
+        let path = dir.0.join(format!("{:020}.log", 0));
+        let mut ends = Vec::new();
+        {
+            let logger = Logger::open(&dir.0, 1, 10).unwrap();
+            let mut w = logger.writer().unwrap();
+            for x in 0..3 {
+                w.write(&[x; 4]).unwrap();
+                ends.push(fs::metadata(&path).unwrap().len() as usize);
+            }
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.drain(ends[0]..ends[1]);
+        fs::write(&path, bytes).unwrap();
+
+        // two records are left, but they span three offsets
+        assert!(matches!(Logger::open(&dir.0, 1, 2), Err(LoggerError::Full { capacity: 2, max_bytes: None })));
+        assert_eq!(Logger::open(&dir.0, 1, 3).unwrap().len(), 3);
+    }
+
+    fn signer() -> ring::signature::Ed25519KeyPair {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn audited_messages_can_be_proven() {
+        use ring::signature::KeyPair;
+
+        let signer = signer();
+        let public_key = signer.public_key().as_ref().to_vec();
+        let logger = Logger::builder(1, 10).audit(signer, 4).build().unwrap();
+        let mut w = logger.writer().unwrap();
+        for x in 0..3 {
+            w.write(format!("{}", x).as_bytes()).unwrap();
+        }
+        assert_eq!(logger.checkpoint().unwrap(), None);
+        w.write(b"3").unwrap();
+        let old = logger.checkpoint().unwrap().unwrap();
+        assert_eq!(old.size, 4);
+        assert!(verify_checkpoint(&public_key, &old));
+
+        w.write(b"4").unwrap();
+        let new = logger.sign_checkpoint().unwrap();
+        assert_eq!(new.size, 5);
+        assert!(verify_checkpoint(&public_key, &new));
+        let mut forged = new.clone();
+        forged.size = 4;
+        assert!(!verify_checkpoint(&public_key, &forged));
+
+        let proof = logger.inclusion_proof(2, new.size).unwrap();
+        assert!(verify_inclusion(b"2", 2, new.size, &proof, &new.root));
+        assert!(!verify_inclusion(b"x", 2, new.size, &proof, &new.root));
+
+        let proof = logger.consistency_proof(old.size, new.size).unwrap();
+        assert!(verify_consistency(old.size, &old.root, new.size, &new.root, &proof));
+
+        assert!(matches!(
+            logger.inclusion_proof(5, 5),
+            Err(LoggerError::OutOfRange { index: 5, size: 5 })
+        ));
+        assert!(matches!(Logger::new(1, 1).unwrap().checkpoint(), Err(LoggerError::Unaudited)));
+    }
+
+    #[test]
+    fn merkle_tree_survives_a_restart() {
+        let dir = TempDir::new();
+        let checkpoint = {
+            let logger = Logger::builder(1, 10).audit(signer(), 0).open(&dir.0).unwrap();
+            let mut w = logger.writer().unwrap();
+            w.write(b"0").unwrap();
+            w.write(b"1").unwrap();
+            logger.sign_checkpoint().unwrap()
+        };
+
+        let logger = Logger::builder(1, 10).audit(signer(), 0).open(&dir.0).unwrap();
+        logger.writer().unwrap().write(b"2").unwrap();
+        let head = logger.sign_checkpoint().unwrap();
+        assert_eq!(head.size, 3);
+        let proof = logger.consistency_proof(checkpoint.size, head.size).unwrap();
+        assert!(verify_consistency(checkpoint.size, &checkpoint.root, 3, &head.root, &proof));
+        let proof = logger.inclusion_proof(0, 3).unwrap();
+        assert!(verify_inclusion(b"0", 0, 3, &proof, &head.root));
+    }
+
+    #[test]
+    fn ed25519_messages_verify_with_the_public_key() {
+        let logger = Logger::builder(1, 10).algorithm(Algorithm::Ed25519).build().unwrap();
+        let public_key = logger.public_key().unwrap();
+        let mut w = logger.writer().unwrap();
+        let mut r = logger.subscribe().unwrap();
+        w.write(b"signed").unwrap();
+
+        let res = r.read().unwrap().unwrap();
+        assert!(res.is_valid);
+        assert!(verify_signature(&public_key, &res.message, &res.hash.bytes));
+        assert!(!verify_signature(&public_key, b"forged", &res.hash.bytes));
+
+        // rotation keeps the algorithm
+        logger.rotate_key().unwrap();
+        assert_ne!(logger.public_key().unwrap(), public_key);
+        w.write(b"rotated").unwrap();
+        assert!(r.read().unwrap().unwrap().is_valid);
+        assert!(Logger::new(1, 1).unwrap().public_key().is_none());
+    }
+
+    #[test]
+    fn ed25519_key_survives_a_restart() {
+        let dir = TempDir::new();
+        let public_key = {
+            let logger = Logger::builder(1, 10).algorithm(Algorithm::Ed25519).open(&dir.0).unwrap();
+            logger.writer().unwrap().write(b"signed").unwrap();
+            logger.public_key().unwrap()
+        };
+
+        let logger = Logger::builder(1, 10).algorithm(Algorithm::Ed25519).open(&dir.0).unwrap();
+        assert_eq!(logger.public_key().unwrap(), public_key);
+        assert!(logger.subscribe().unwrap().read().unwrap().unwrap().is_valid);
+    }
+
+    #[test]
+    fn crc32c_logger_detects_corruption() {
+        let dir = TempDir::new();
+        {
+            let logger = Logger::builder(1, 10).integrity(Crc32c).open(&dir.0).unwrap();
+            let mut w = logger.writer().unwrap();
+            w.write(b"first").unwrap();
+            w.write(b"second").unwrap();
+        }
+
+        // flip the last byte of the second payload
+        let path = dir.0.join(format!("{:020}.log", 0));
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let logger = Logger::builder(1, 10).integrity(Crc32c).open(&dir.0).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        let first = r.read().unwrap().unwrap();
+        assert!(first.is_valid);
+        assert_eq!(first.hash, crc32c(b"first"));
+        assert!(!r.read().unwrap().unwrap().is_valid);
+    }
+
+    // A stand-in for a scheme with long tags, like RSA signatures.
+    struct LongTags;
+
+    impl Integrity for LongTags {
+        type Tag = Vec<u8>;
+
+        fn sign(&self, data: &[u8]) -> Vec<u8> {
+            data.iter().cycle().take(512).copied().collect()
+        }
+
+        fn verify(&self, data: &[u8], tag: &Vec<u8>) -> bool {
+            self.sign(data) == *tag
+        }
+
+        fn encode(tag: &Vec<u8>) -> Vec<u8> {
+            tag.clone()
+        }
+
+        fn decode(bytes: &[u8]) -> Option<Vec<u8>> {
+            Some(bytes.to_vec())
+        }
+    }
+
+    #[test]
+    fn long_tags_survive_a_restart() {
+        let dir = TempDir::new();
+        let builder = || Logger::builder(1, 10).integrity(LongTags).chained(true);
+        {
+            let logger = builder().open(&dir.0).unwrap();
+            let mut w = logger.writer().unwrap();
+            w.write(b"first").unwrap();
+            w.write(b"second").unwrap();
+        }
+
+        let logger = builder().open(&dir.0).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        for message in [&b"first"[..], b"second"] {
+            let res = r.read().unwrap().unwrap();
+            assert_eq!((&*res.message, res.hash.len()), (message, 512));
+            assert!(res.is_valid && !res.chain_break);
+        }
+    }
+
+    #[test]
+    fn unchecked_messages_are_always_valid() {
+        let logger = Logger::builder(1, 10).integrity(NoIntegrity).build().unwrap();
+        logger.writer().unwrap().write(b"data").unwrap();
+        let res: Response<()> = logger.subscribe().unwrap().read().unwrap().unwrap();
+        assert!(res.is_valid);
+    }
+
+    #[test]
+    fn hmac_sha512_keys_are_used() {
+        let logger = Logger::builder(1, 10).algorithm(Algorithm::HmacSha512).build().unwrap();
+        logger.writer().unwrap().write(b"data").unwrap();
+        let res = logger.subscribe().unwrap().read().unwrap().unwrap();
+        assert!(res.is_valid);
+        assert_eq!(res.hash.bytes.len(), 64);
+    }
+
+    #[test]
+    fn encrypted_messages_are_decrypted_on_read() {
+        let dir = TempDir::new();
+        let key = EncryptionKey::new(Cipher::ChaCha20Poly1305, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+        {
+            let logger = Logger::builder(1, 10).encryption(key.clone()).open(&dir.0).unwrap();
+            logger.writer().unwrap().write(b"top secret").unwrap();
+        }
+        let segment = fs::read(dir.0.join(format!("{:020}.log", 0))).unwrap();
+        assert!(!segment.windows(10).any(|w| w == b"top secret"));
+
+        let logger = Logger::builder(1, 10).encryption(key).open(&dir.0).unwrap();
+        let res = logger.subscribe().unwrap().read().unwrap().unwrap();
+        assert!(res.is_valid);
+        assert_eq!(&*res.message, b"top secret");
+    }
+
+    #[test]
+    fn wrong_encryption_key_fails_to_decrypt() {
+        let dir = TempDir::new();
+        {
+            let key = EncryptionKey::new(Cipher::Aes256Gcm, &[1; ENCRYPTION_KEY_LEN]).unwrap();
+            let logger = Logger::builder(1, 10).encryption(key).open(&dir.0).unwrap();
+            let mut w = logger.writer().unwrap();
+            w.write(b"first").unwrap();
+            w.write(b"second").unwrap();
+        }
+
+        let key = EncryptionKey::new(Cipher::Aes256Gcm, &[2; ENCRYPTION_KEY_LEN]).unwrap();
+        let logger = Logger::builder(1, 10).encryption(key).open(&dir.0).unwrap();
+        let mut r = logger.subscribe().unwrap();
+        assert!(matches!(r.read(), Err(LoggerError::Decryption { offset: 0 })));
+        // the reader moves on to the next message
+        assert!(matches!(r.read(), Err(LoggerError::Decryption { offset: 1 })));
+        assert!(logger.is_empty());
+    }
+
+    #[test]
+    fn readers_share_the_payload() {
+        let logger = Logger::new(2, 10).unwrap();
+        logger.writer().unwrap().write(&[1; 1024]).unwrap();
+        let (mut r1, mut r2) = (logger.subscribe().unwrap(), logger.subscribe().unwrap());
+        let a = r1.read().unwrap().unwrap();
+        let b = r2.read().unwrap().unwrap();
+        assert!(Arc::ptr_eq(&a.message, &b.message));
+    }
+
+    #[test]
+    fn handles_are_send() {
+        fn assert_send<T: Send>() {}
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Logger>();
+        assert_send::<Writer>();
+        assert_send::<Reader>();
     }
 }
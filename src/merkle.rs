@@ -0,0 +1,514 @@
+use crate::storage::Recovered;
+use ring::digest::{self, SHA256};
+use ring::signature::{self, Ed25519KeyPair};
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A node of the Merkle tree: a SHA-256 hash.
+pub type Hash = [u8; 32];
+
+// what a checkpoint signature covers, followed by the tree size and root
+const CHECKPOINT_CONTEXT: &[u8] = b"spmc-logger checkpoint v1\n";
+
+/// A signed tree head: the root of the Merkle tree over the first `size`
+/// messages, signed with the logger's Ed25519 key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub size: u64,
+    pub root: Hash,
+    pub signature: Vec<u8>,
+}
+
+impl Checkpoint {
+    fn signed_bytes(size: u64, root: &Hash) -> Vec<u8> {
+        let mut buf = CHECKPOINT_CONTEXT.to_vec();
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(root);
+        buf
+    }
+}
+
+/// Hash of the leaf for a message, as in RFC 6962.
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    hash(&[&[0], data])
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    hash(&[&[1], left, right])
+}
+
+fn hash(parts: &[&[u8]]) -> Hash {
+    let mut ctx = digest::Context::new(&SHA256);
+    for part in parts {
+        ctx.update(part);
+    }
+    ctx.finish().as_ref().try_into().unwrap()
+}
+
+/// Checks the signature on `checkpoint` with the logger's Ed25519 public key.
+pub fn verify_checkpoint(public_key: &[u8], checkpoint: &Checkpoint) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(
+            &Checkpoint::signed_bytes(checkpoint.size, &checkpoint.root),
+            &checkpoint.signature,
+        )
+        .is_ok()
+}
+
+/// Checks that the message `data` at `offset` is in the tree with `root` and
+/// `size` leaves, given a proof from
+/// [`Logger::inclusion_proof`](crate::Logger::inclusion_proof).
+pub fn verify_inclusion(data: &[u8], offset: u64, size: u64, proof: &[Hash], root: &Hash) -> bool {
+    if offset >= size {
+        return false;
+    }
+    // RFC 9162, section 2.1.3.2
+    let (mut f, mut s) = (offset, size - 1);
+    let mut r = leaf_hash(data);
+    for p in proof {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(p, &r);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && r == *root
+}
+
+/// Checks that the tree with `new_root` and `new_size` leaves extends the
+/// one with `old_root` and `old_size` leaves, given a proof from
+/// [`Logger::consistency_proof`](crate::Logger::consistency_proof).
+pub fn verify_consistency(
+    old_size: u64,
+    old_root: &Hash,
+    new_size: u64,
+    new_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        // the empty tree is a prefix of every tree
+        return proof.is_empty();
+    }
+
+    // RFC 9162, section 2.1.4.2
+    let mut path = proof.to_vec();
+    if old_size.is_power_of_two() {
+        path.insert(0, *old_root);
+    }
+    let (mut f, mut s) = (old_size - 1, new_size - 1);
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut fr, mut sr) = (*first, *first);
+    for c in rest {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && fr == *old_root && sr == *new_root
+}
+
+// Levels of a persistent tree that are only kept for the newest leaves.
+// Older subtrees of up to 2^DISK_LEVELS leaves are hashed again from the leaf
+// file when a proof needs them.
+const DISK_LEVELS: usize = 10;
+
+/// The Merkle tree over every message written, leaf `i` being the message at
+/// offset `i`. Every complete, aligned subtree is cached, so roots and proofs
+/// only hash O(log n) nodes. A persistent tree only caches the subtrees of
+/// 2^DISK_LEVELS leaves and up for older leaves, which keeps it to about 64
+/// bytes per 1024 messages.
+pub(crate) struct Tree {
+    // levels[k][i] is the root of the subtree over leaves i * 2^k..(i + 1) * 2^k,
+    // less the first `dropped >> k` of them for k < DISK_LEVELS
+    levels: Vec<Vec<Hash>>,
+    // leaves whose lower levels only live in the file, a multiple of
+    // 2^DISK_LEVELS
+    dropped: u64,
+    // leaf hashes are appended here on a persistent logger
+    file: Option<File>,
+}
+
+impl Tree {
+    pub(crate) fn new() -> Self {
+        Tree { levels: vec![Vec::new()], dropped: 0, file: None }
+    }
+
+    /// Opens the leaf file at `path`, dropping leaves for messages that did
+    /// not make it into the log and adding the ones that are missing.
+    pub(crate) fn open(path: &Path, recovered: &Recovered) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut tree = Tree::new();
+        for leaf in bytes.chunks_exact(32).take(recovered.tail as usize) {
+            tree.insert(leaf.try_into().unwrap());
+        }
+        file.set_len(tree.len() * 32)?;
+        tree.file = Some(file);
+
+        // the writer saves the leaves before the records, so a crash in
+        // between leaves the tree ahead, which is cut back to the log above.
+        // A tree that is behind the log gets the missing leaves back.
+        for record in &recovered.records {
+            if record.offset == tree.len() {
+                tree.push(&record.payload)?;
+            }
+        }
+        if tree.len() < recovered.tail {
+            return Err(io::Error::new(ErrorKind::InvalidData, "the Merkle tree is missing leaves"));
+        }
+        tree.forget();
+        Ok(tree)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.dropped + self.levels[0].len() as u64
+    }
+
+    /// Adds the leaf for `data`, saving it first on a persistent logger.
+    pub(crate) fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        self.extend(&[data])
+    }
+
+    /// Adds the leaves for every message in `batch`, saving them first on a
+    /// persistent logger. Nothing is added if saving fails.
+    pub(crate) fn extend<D: AsRef<[u8]>>(&mut self, batch: &[D]) -> io::Result<()> {
+        // not after, so the batch can still be taken back by `truncate`
+        self.forget();
+        let leaves: Vec<Hash> = batch.iter().map(|data| leaf_hash(data.as_ref())).collect();
+        let len = self.len();
+        if let Some(file) = &mut self.file {
+            // over whatever a failed write or a truncation left behind
+            file.seek(SeekFrom::Start(len * 32))?;
+            file.write_all(leaves.as_flattened())?;
+        }
+        for leaf in leaves {
+            self.insert(leaf);
+        }
+        Ok(())
+    }
+
+    /// Drops every leaf from `len` on, as long as it is no further back than
+    /// the last `extend` started. Their hashes stay in the file until new
+    /// leaves are written over them, and are ignored on restart as long as
+    /// the log does not reach them.
+    pub(crate) fn truncate(&mut self, len: u64) {
+        for (k, level) in self.levels.iter_mut().enumerate() {
+            let kept = if k < DISK_LEVELS { self.dropped >> k } else { 0 };
+            level.truncate(((len >> k) - kept) as usize);
+        }
+    }
+
+    // Leaves the lower levels of every complete subtree of 2^DISK_LEVELS
+    // leaves to the file.
+    fn forget(&mut self) {
+        if self.file.is_none() {
+            return;
+        }
+        let dropped = self.len() >> DISK_LEVELS << DISK_LEVELS;
+        for (k, level) in self.levels.iter_mut().enumerate().take(DISK_LEVELS) {
+            level.drain(..((dropped - self.dropped) >> k) as usize);
+        }
+        self.dropped = dropped;
+    }
+
+    fn insert(&mut self, leaf: Hash) {
+        self.levels[0].push(leaf);
+        let mut k = 0;
+        // every second node completes a subtree one level up, and the lower
+        // levels only ever drop pairs
+        while self.levels[k].len().is_multiple_of(2) {
+            let n = self.levels[k].len();
+            let parent = node_hash(&self.levels[k][n - 2], &self.levels[k][n - 1]);
+            if self.levels.len() == k + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[k + 1].push(parent);
+            k += 1;
+        }
+    }
+
+    // Root of the subtree over leaves i * 2^k..(i + 1) * 2^k.
+    fn node(&self, k: usize, i: u64) -> io::Result<Hash> {
+        let kept = if k < DISK_LEVELS { self.dropped >> k } else { 0 };
+        if i >= kept {
+            return Ok(self.levels[k][(i - kept) as usize]);
+        }
+        let mut file = self.file.as_ref().unwrap();
+        let mut leaves = vec![[0; 32]; 1 << k];
+        file.seek(SeekFrom::Start((i << k) * 32))?;
+        file.read_exact(leaves.as_flattened_mut())?;
+        while leaves.len() > 1 {
+            leaves = leaves.chunks_exact(2).map(|pair| node_hash(&pair[0], &pair[1])).collect();
+        }
+        Ok(leaves[0])
+    }
+
+    /// Root of the tree over the first `size` leaves.
+    pub(crate) fn root(&self, size: u64) -> io::Result<Hash> {
+        if size == 0 {
+            return Ok(hash(&[]));
+        }
+        self.subtree(0, size)
+    }
+
+    // Root of the subtree over leaves `lo..hi`, following RFC 6962's split at
+    // the largest power of two below the size.
+    fn subtree(&self, lo: u64, hi: u64) -> io::Result<Hash> {
+        let n = hi - lo;
+        if n.is_power_of_two() && lo.is_multiple_of(n) {
+            return self.node(n.trailing_zeros() as usize, lo / n);
+        }
+        let k = split(n);
+        Ok(node_hash(&self.subtree(lo, lo + k)?, &self.subtree(lo + k, hi)?))
+    }
+
+    /// Audit path for leaf `index` in the tree over the first `size` leaves.
+    pub(crate) fn inclusion(&self, index: u64, size: u64) -> io::Result<Vec<Hash>> {
+        let mut proof = Vec::new();
+        self.path(index, 0, size, &mut proof)?;
+        Ok(proof)
+    }
+
+    fn path(&self, m: u64, lo: u64, hi: u64, proof: &mut Vec<Hash>) -> io::Result<()> {
+        let n = hi - lo;
+        if n <= 1 {
+            return Ok(());
+        }
+        let k = split(n);
+        if m < k {
+            self.path(m, lo, lo + k, proof)?;
+            proof.push(self.subtree(lo + k, hi)?);
+        } else {
+            self.path(m - k, lo + k, hi, proof)?;
+            proof.push(self.subtree(lo, lo + k)?);
+        }
+        Ok(())
+    }
+
+    /// Proof that the tree over the first `old` leaves is a prefix of the one
+    /// over the first `new`.
+    pub(crate) fn consistency(&self, old: u64, new: u64) -> io::Result<Vec<Hash>> {
+        let mut proof = Vec::new();
+        if 0 < old && old < new {
+            self.subproof(old, 0, new, true, &mut proof)?;
+        }
+        Ok(proof)
+    }
+
+    fn subproof(&self, m: u64, lo: u64, hi: u64, whole: bool, proof: &mut Vec<Hash>) -> io::Result<()> {
+        let n = hi - lo;
+        if m == n {
+            if !whole {
+                proof.push(self.subtree(lo, hi)?);
+            }
+            return Ok(());
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(m, lo, lo + k, whole, proof)?;
+            proof.push(self.subtree(lo + k, hi)?);
+        } else {
+            self.subproof(m - k, lo + k, hi, false, proof)?;
+            proof.push(self.subtree(lo, lo + k)?);
+        }
+        Ok(())
+    }
+}
+
+// The largest power of two below `n`.
+fn split(n: u64) -> u64 {
+    1 << (63 - (n - 1).leading_zeros())
+}
+
+/// The Merkle tree of an audited logger and the key its checkpoints are
+/// signed with.
+pub(crate) struct Audit {
+    pub(crate) tree: Tree,
+    signer: Arc<Ed25519KeyPair>,
+    // sign a checkpoint every this many messages, never if 0
+    interval: u64,
+    pub(crate) checkpoint: Option<Checkpoint>,
+}
+
+impl Audit {
+    pub(crate) fn new(tree: Tree, signer: Arc<Ed25519KeyPair>, interval: u64) -> Self {
+        Audit { tree, signer, interval, checkpoint: None }
+    }
+
+    /// Signs the current tree head and keeps it as the latest checkpoint.
+    pub(crate) fn sign(&mut self) -> io::Result<Checkpoint> {
+        let size = self.tree.len();
+        let root = self.tree.root(size)?;
+        let signature = self.signer.sign(&Checkpoint::signed_bytes(size, &root));
+        let checkpoint = Checkpoint { size, root, signature: signature.as_ref().to_vec() };
+        self.checkpoint = Some(checkpoint.clone());
+        Ok(checkpoint)
+    }
+
+    /// Signs a checkpoint if one became due while the tree grew from
+    /// `old_len` leaves.
+    pub(crate) fn written(&mut self, old_len: u64) -> io::Result<()> {
+        if self.interval > 0 && old_len / self.interval != self.tree.len() / self.interval {
+            self.sign()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(n: u64) -> Tree {
+        let mut tree = Tree::new();
+        for x in 0..n {
+            tree.push(&x.to_le_bytes()).unwrap();
+        }
+        tree
+    }
+
+    // The root straight from the definition in RFC 6962.
+    fn reference_root(leaves: &[Hash]) -> Hash {
+        match leaves.len() {
+            0 => hash(&[]),
+            1 => leaves[0],
+            n => {
+                let k = split(n as u64) as usize;
+                node_hash(&reference_root(&leaves[..k]), &reference_root(&leaves[k..]))
+            }
+        }
+    }
+
+    #[test]
+    fn roots_match_the_definition() {
+        let tree = tree(20);
+        assert_eq!(hex(&tree.root(0).unwrap()), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        for size in 0..=20 {
+            let leaves: Vec<_> = (0..size).map(|x: u64| leaf_hash(&x.to_le_bytes())).collect();
+            assert_eq!(tree.root(size).unwrap(), reference_root(&leaves));
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        let tree = tree(13);
+        for size in 1..=13 {
+            let root = tree.root(size).unwrap();
+            for index in 0..size {
+                let proof = tree.inclusion(index, size).unwrap();
+                let data = index.to_le_bytes();
+                assert!(verify_inclusion(&data, index, size, &proof, &root));
+                assert!(!verify_inclusion(b"other", index, size, &proof, &root));
+                assert!(!verify_inclusion(&data, index ^ 1, size, &proof, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_leaves_are_written_over() {
+        let dir = crate::storage::tests::TempDir::new();
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("tree");
+        let recovered = |tail| Recovered { head: 0, tail, records: Vec::new(), last_tag: Vec::new(), cursors: Vec::new() };
+        let mut tree = Tree::open(&path, &recovered(0)).unwrap();
+        for x in 0..5u64 {
+            tree.push(&x.to_le_bytes()).unwrap();
+        }
+        tree.truncate(3);
+        assert_eq!((tree.len(), tree.root(3).unwrap()), (3, self::tree(3).root(3).unwrap()));
+        tree.extend(&[3u64.to_le_bytes(), 4u64.to_le_bytes(), 5u64.to_le_bytes()]).unwrap();
+        assert_eq!(tree.root(6).unwrap(), self::tree(6).root(6).unwrap());
+        drop(tree);
+
+        let tree = Tree::open(&path, &recovered(6)).unwrap();
+        assert_eq!(tree.root(6).unwrap(), self::tree(6).root(6).unwrap());
+    }
+
+    #[test]
+    fn old_subtrees_are_read_back_from_the_file() {
+        let dir = crate::storage::tests::TempDir::new();
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let recovered = Recovered { head: 0, tail: 0, records: Vec::new(), last_tag: Vec::new(), cursors: Vec::new() };
+        let mut tree = Tree::open(&dir.0.join("tree"), &recovered).unwrap();
+        let size: u64 = 3 << DISK_LEVELS | 5;
+        for x in 0..size {
+            tree.push(&x.to_le_bytes()).unwrap();
+        }
+        assert_eq!(tree.dropped, 3 << DISK_LEVELS);
+        assert!(tree.levels[0].len() < 1 << DISK_LEVELS);
+
+        let memory = self::tree(size);
+        for (index, old) in [(0, size), (1000, 2000), (size - 1, size), (2047, 2049)] {
+            assert_eq!(tree.root(old).unwrap(), memory.root(old).unwrap());
+            assert_eq!(tree.inclusion(index, old).unwrap(), memory.inclusion(index, old).unwrap());
+            assert_eq!(tree.consistency(index + 1, old).unwrap(), memory.consistency(index + 1, old).unwrap());
+        }
+
+        // a batch that completes a subtree can still be taken back
+        let batch: Vec<_> = (size..size + 1100).map(u64::to_le_bytes).collect();
+        tree.extend(&batch).unwrap();
+        tree.truncate(size);
+        assert_eq!(tree.root(size).unwrap(), memory.root(size).unwrap());
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let tree = tree(13);
+        for new in 1..=13 {
+            for old in 1..=new {
+                let proof = tree.consistency(old, new).unwrap();
+                let (old_root, new_root) = (tree.root(old).unwrap(), tree.root(new).unwrap());
+                assert!(verify_consistency(old, &old_root, new, &new_root, &proof));
+                if old < new {
+                    let wrong = tree.root(old - 1).unwrap();
+                    assert!(!verify_consistency(old, &wrong, new, &new_root, &proof));
+                }
+            }
+        }
+    }
+}
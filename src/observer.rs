@@ -0,0 +1,122 @@
+use crate::LoggerError;
+use std::fmt;
+use std::sync::Arc;
+
+/// Hooks called as messages move through a logger, for logging and metrics.
+/// Every method does nothing by default, so an observer only implements the
+/// events it cares about. Hooks run on the writer's and readers' threads, in
+/// the middle of a write or read, so they should be cheap.
+///
+/// Set one with [`Builder::observer`](crate::Builder::observer). With the
+/// `tracing` feature, loggers report to `Tracing` unless told otherwise.
+///
+/// A logger's topics report to its observer too. Every hook is passed the
+/// `topic` the event happened in, or `None` for the logger itself.
+pub trait Observer: Send + Sync + 'static {
+    /// A message of `len` bytes was written at `offset`.
+    fn on_write(&self, _topic: Option<&str>, _offset: u64, _len: usize) {}
+
+    /// The reader in slot `reader` read the message at `offset`.
+    fn on_read(&self, _topic: Option<&str>, _reader: usize, _offset: u64) {}
+
+    /// A write found no room for its messages in a buffer of `capacity`. The
+    /// backpressure policy decides what happens next.
+    fn on_full(&self, _topic: Option<&str>, _capacity: usize) {}
+
+    /// A write or a subscription was turned away with `error`.
+    fn on_reject(&self, _topic: Option<&str>, _error: &LoggerError) {}
+
+    /// The message at `offset` left the buffer. `unread` is set when it was
+    /// overwritten before the whole quorum had read it.
+    fn on_evict(&self, _topic: Option<&str>, _offset: u64, _unread: bool) {}
+
+    /// Something went wrong that could not be returned to a caller, like
+    /// failing to persist the head of the log.
+    fn on_error(&self, _topic: Option<&str>, _error: &LoggerError) {}
+}
+
+impl fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+// so the caller can keep a handle on the observer it gave the logger
+impl<O: Observer + ?Sized> Observer for Arc<O> {
+    fn on_write(&self, topic: Option<&str>, offset: u64, len: usize) {
+        (**self).on_write(topic, offset, len)
+    }
+
+    fn on_read(&self, topic: Option<&str>, reader: usize, offset: u64) {
+        (**self).on_read(topic, reader, offset)
+    }
+
+    fn on_full(&self, topic: Option<&str>, capacity: usize) {
+        (**self).on_full(topic, capacity)
+    }
+
+    fn on_reject(&self, topic: Option<&str>, error: &LoggerError) {
+        (**self).on_reject(topic, error)
+    }
+
+    fn on_evict(&self, topic: Option<&str>, offset: u64, unread: bool) {
+        (**self).on_evict(topic, offset, unread)
+    }
+
+    fn on_error(&self, topic: Option<&str>, error: &LoggerError) {
+        (**self).on_error(topic, error)
+    }
+}
+
+/// Ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// Emits every event to [`tracing`], under the `spmc_logger` target, with a
+/// `topic` field for events in a topic. Messages moving through the logger
+/// are logged at `TRACE`, a full buffer and turned away writers and readers
+/// at `DEBUG`, and messages lost before they were read and failures at
+/// `WARN`.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Observer for Tracing {
+    fn on_write(&self, topic: Option<&str>, offset: u64, len: usize) {
+        tracing::trace!(target: "spmc_logger", topic, offset, len, "message written");
+    }
+
+    fn on_read(&self, topic: Option<&str>, reader: usize, offset: u64) {
+        tracing::trace!(target: "spmc_logger", topic, reader, offset, "message read");
+    }
+
+    fn on_full(&self, topic: Option<&str>, capacity: usize) {
+        tracing::debug!(target: "spmc_logger", topic, capacity, "buffer is full");
+    }
+
+    fn on_reject(&self, topic: Option<&str>, error: &LoggerError) {
+        tracing::debug!(target: "spmc_logger", topic, %error, "rejected");
+    }
+
+    fn on_evict(&self, topic: Option<&str>, offset: u64, unread: bool) {
+        if unread {
+            tracing::warn!(target: "spmc_logger", topic, offset, "overwrote an unread message");
+        } else {
+            tracing::trace!(target: "spmc_logger", topic, offset, "message evicted");
+        }
+    }
+
+    fn on_error(&self, topic: Option<&str>, error: &LoggerError) {
+        tracing::warn!(target: "spmc_logger", topic, %error, "logger error");
+    }
+}
+
+pub(crate) fn default_observer() -> Arc<dyn Observer> {
+    #[cfg(feature = "tracing")]
+    return Arc::new(Tracing);
+    #[cfg(not(feature = "tracing"))]
+    Arc::new(NoObserver)
+}
@@ -0,0 +1,183 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+// Running totals behind `Logger::stats`. They only feed the snapshot, so
+// relaxed ordering is enough.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) written: AtomicU64,
+    pub(crate) read: AtomicU64,
+    pub(crate) evicted: AtomicU64,
+    pub(crate) overwritten: AtomicU64,
+    pub(crate) rejected: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn written(&self, n: u64) {
+        self.written.fetch_add(n, Relaxed);
+    }
+
+    pub(crate) fn read(&self, n: u64) {
+        self.read.fetch_add(n, Relaxed);
+    }
+
+    pub(crate) fn evicted(&self, unread: bool) {
+        self.evicted.fetch_add(1, Relaxed);
+        if unread {
+            self.overwritten.fetch_add(1, Relaxed);
+        }
+    }
+
+    pub(crate) fn rejected(&self) {
+        self.rejected.fetch_add(1, Relaxed);
+    }
+}
+
+/// A snapshot of a logger's buffer and readers, from
+/// [`Logger::stats`](crate::Logger::stats). The totals count from when the
+/// logger was created or opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Messages the buffer can hold.
+    pub capacity: usize,
+    /// Messages currently retained.
+    pub occupancy: usize,
+    /// The byte budget, if the logger has one.
+    pub max_bytes: Option<usize>,
+    /// Bytes taken up by the retained messages, counted against the budget.
+    pub bytes: usize,
+    /// Offset of the oldest retained message.
+    pub head: u64,
+    /// Offset the next message will be written to.
+    pub tail: u64,
+    pub written: u64,
+    /// Reads by every reader together, so a message read by the whole quorum
+    /// counts `num_readers` times.
+    pub read: u64,
+    /// Messages that left the buffer, read or not.
+    pub evicted: u64,
+    /// Messages evicted before the whole quorum had read them.
+    pub overwritten: u64,
+    /// Writes that failed because the buffer was full, including dropped ones.
+    pub rejected: u64,
+    /// Messages thrown away by
+    /// [`Backpressure::DropNewest`](crate::Backpressure::DropNewest).
+    pub dropped: u64,
+    /// One entry per slot of the quorum.
+    pub readers: Vec<ReaderStats>,
+}
+
+/// Where one slot of the quorum is in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReaderStats {
+    pub slot: usize,
+    /// Set for a durable reader's slot.
+    pub name: Option<String>,
+    /// Whether a reader is subscribed in the slot right now.
+    pub active: bool,
+    /// Readers subscribed in the slot: more than one for a consumer group.
+    pub members: usize,
+    /// Offset of the next message the slot will read.
+    pub offset: u64,
+    /// Retained messages the slot has not read yet.
+    pub lag: u64,
+}
+
+impl Stats {
+    /// Renders the snapshot in the Prometheus text exposition format, with
+    /// every metric name prefixed by `spmc_logger_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let gauges = [
+            ("capacity", "Messages the buffer can hold.", self.capacity as u64),
+            ("occupancy", "Messages currently retained.", self.occupancy as u64),
+            ("head", "Offset of the oldest retained message.", self.head),
+            ("tail", "Offset the next message will be written to.", self.tail),
+        ];
+        let bytes = [
+            ("bytes", "Bytes taken up by the retained messages.", Some(self.bytes)),
+            ("max_bytes", "Bytes the retained messages can take up.", self.max_bytes),
+        ];
+        let bytes = bytes.into_iter().filter_map(|(name, help, value)| Some((name, help, value? as u64)));
+        for (name, help, value) in gauges.into_iter().chain(bytes) {
+            metric(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "spmc_logger_{} {}", name, value);
+        }
+        let counters = [
+            ("written_total", "Messages written.", self.written),
+            ("read_total", "Messages read, by every reader together.", self.read),
+            ("evicted_total", "Messages that left the buffer.", self.evicted),
+            ("overwritten_total", "Messages evicted before every reader read them.", self.overwritten),
+            ("rejected_total", "Writes that failed because the buffer was full.", self.rejected),
+            ("dropped_total", "Messages thrown away because the buffer was full.", self.dropped),
+        ];
+        for (name, help, value) in counters {
+            metric(&mut out, name, "counter", help);
+            let _ = writeln!(out, "spmc_logger_{} {}", name, value);
+        }
+
+        type Value = fn(&ReaderStats) -> u64;
+        let readers: [(&str, &str, Value); 4] = [
+            ("reader_offset", "Offset of the next message the reader slot will read.", |r| r.offset),
+            ("reader_lag", "Retained messages the reader slot has not read yet.", |r| r.lag),
+            ("reader_active", "Whether a reader is subscribed in the slot.", |r| r.active as u64),
+            ("reader_members", "Readers subscribed in the slot.", |r| r.members as u64),
+        ];
+        for (name, help, value) in readers {
+            metric(&mut out, name, "gauge", help);
+            for reader in &self.readers {
+                let _ = write!(out, "spmc_logger_{}{{slot=\"{}\"", name, reader.slot);
+                if let Some(reader_name) = &reader.name {
+                    let _ = write!(out, ",name=\"{}\"", escape(reader_name));
+                }
+                let _ = writeln!(out, "}} {}", value(reader));
+            }
+        }
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP spmc_logger_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE spmc_logger_{} {}", name, kind);
+}
+
+// label values may not contain raw backslashes, quotes or newlines
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let stats = Stats {
+            capacity: 10,
+            occupancy: 2,
+            max_bytes: None,
+            bytes: 130,
+            head: 3,
+            tail: 5,
+            written: 5,
+            read: 4,
+            evicted: 3,
+            overwritten: 1,
+            rejected: 0,
+            dropped: 0,
+            readers: vec![
+                ReaderStats { slot: 0, name: None, active: true, members: 1, offset: 5, lag: 0 },
+                ReaderStats { slot: 1, name: Some("a\"b".into()), active: false, members: 0, offset: 3, lag: 2 },
+            ],
+        };
+        let text = stats.to_prometheus();
+        assert!(text.contains("# TYPE spmc_logger_occupancy gauge\nspmc_logger_occupancy 2\n"));
+        assert!(text.contains("spmc_logger_bytes 130\n"));
+        assert!(!text.contains("spmc_logger_max_bytes"));
+        assert!(text.contains("# TYPE spmc_logger_written_total counter\nspmc_logger_written_total 5\n"));
+        assert!(text.contains("spmc_logger_reader_lag{slot=\"0\"} 0\n"));
+        assert!(text.contains("spmc_logger_reader_lag{slot=\"1\",name=\"a\\\"b\"} 2\n"));
+        assert!(text.contains("spmc_logger_reader_active{slot=\"1\",name=\"a\\\"b\"} 0\n"));
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Segments are rolled over once they grow past this many bytes.
+pub(crate) const SEGMENT_BYTES: u64 = 64 << 20;
+
+// length (u32) + offset (u64) + tag length (u16) + previous tag length (u16)
+const RECORD_HEADER: usize = 4 + 8 + 2 + 2;
+
+/// A message as it is framed in a segment file.
+pub(crate) struct Record {
+    pub(crate) offset: u64,
+    pub(crate) tag: Vec<u8>,
+    /// Tag of the message before this one, if the log is chained.
+    pub(crate) prev: Vec<u8>,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// A message to be appended, borrowing its parts.
+pub(crate) struct Entry<'a> {
+    pub(crate) offset: u64,
+    pub(crate) tag: &'a [u8],
+    pub(crate) prev: &'a [u8],
+    pub(crate) payload: &'a [u8],
+}
+
+/// What was found on disk when the log was opened.
+pub(crate) struct Recovered {
+    /// Offset of the oldest message that had not reached the quorum.
+    pub(crate) head: u64,
+    /// Offset the next message will be written to.
+    pub(crate) tail: u64,
+    /// Every record from `head` onwards, in offset order.
+    pub(crate) records: Vec<Record>,
+    /// Tag of the newest record, even if it is before `head`, so a chained
+    /// log carries on from it.
+    pub(crate) last_tag: Vec<u8>,
+    /// The last checkpoint of every durable reader, by name.
+    pub(crate) cursors: Vec<(String, u64)>,
+}
+
+/// The checkpoint file of one durable reader, holding the offset of the next
+/// message it will read.
+pub(crate) struct CursorFile(File);
+
+impl CursorFile {
+    pub(crate) fn store(&mut self, offset: u64) -> io::Result<()> {
+        self.0.seek(SeekFrom::Start(0))?;
+        self.0.write_all(&offset.to_le_bytes())
+    }
+}
+
+struct Segments {
+    // base offsets of the older, read-only segments
+    sealed: VecDeque<u64>,
+    active: File,
+    active_base: u64,
+    active_len: u64,
+}
+
+/// Append-only segment files plus a small `head` file recording how far
+/// eviction has got. Each segment is named after the offset of its first
+/// record, so whole segments can be deleted once the head moves past them.
+pub(crate) struct Storage {
+    dir: PathBuf,
+    segment_bytes: u64,
+    segments: Mutex<Segments>,
+    // the head file and the last head written to it
+    head: Mutex<(File, u64)>,
+}
+
+impl Storage {
+    pub(crate) fn open(dir: &Path, segment_bytes: u64) -> io::Result<(Storage, Recovered)> {
+        fs::create_dir_all(dir)?;
+
+        let mut head_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join("head"))?;
+        let mut buf = [0u8; 8];
+        let head = match head_file.read_exact(&mut buf) {
+            Ok(()) => u64::from_le_bytes(buf),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e),
+        };
+
+        let cursors_dir = dir.join("cursors");
+        fs::create_dir_all(&cursors_dir)?;
+        let mut cursors = Vec::new();
+        for entry in fs::read_dir(&cursors_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_str().and_then(decode_name);
+            let offset = fs::read(entry.path())?
+                .get(..8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+            if let (Some(name), Some(offset)) = (name, offset) {
+                cursors.push((name, offset.max(head)));
+            }
+        }
+        cursors.sort();
+
+        let mut bases = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let base = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".log"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(base) = base {
+                bases.push(base);
+            }
+        }
+        bases.sort_unstable();
+
+        let mut records = Vec::new();
+        let mut last_tag = Vec::new();
+        let mut tail = head;
+        let mut sealed = VecDeque::new();
+        let mut active = None;
+        for (i, &base) in bases.iter().enumerate() {
+            let path = segment_path(dir, base);
+            // everything in this segment has been evicted
+            if bases.get(i + 1).is_some_and(|&next| next <= head) {
+                fs::remove_file(path)?;
+                continue;
+            }
+
+            let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            let mut pos = 0;
+            while let Some((record, len)) = decode(&bytes[pos..]) {
+                tail = tail.max(record.offset + 1);
+                if record.offset >= head {
+                    records.push(record);
+                } else {
+                    last_tag = record.tag;
+                }
+                pos += len;
+            }
+            if i + 1 == bases.len() {
+                // anything after the last complete record is a torn write
+                file.set_len(pos as u64)?;
+                active = Some((file, base, pos as u64));
+            } else if pos < bytes.len() {
+                // only the active segment is written to, so a sealed one
+                // was damaged some other way: don't throw the rest away
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("segment {} is corrupt at byte {}", base, pos),
+                ));
+            } else {
+                sealed.push_back(base);
+            }
+        }
+
+        let (active, active_base, active_len) = match active {
+            Some(active) => active,
+            None => (create_segment(dir, tail)?, tail, 0),
+        };
+        let mut segments = Segments { sealed, active, active_base, active_len };
+        segments.active.seek(SeekFrom::End(0))?;
+
+        let storage = Storage {
+            dir: dir.to_path_buf(),
+            segment_bytes,
+            segments: Mutex::new(segments),
+            head: Mutex::new((head_file, head)),
+        };
+        if let Some(record) = records.last() {
+            last_tag = record.tag.clone();
+        }
+        Ok((storage, Recovered { head, tail, records, last_tag, cursors }))
+    }
+
+    /// Appends the records for `entries` to the active segment in a single
+    /// write, rolling over to a new segment first if the active one is full.
+    pub(crate) fn append(&self, entries: &[Entry]) -> io::Result<()> {
+        let Some(offset) = entries.first().map(|e| e.offset) else {
+            return Ok(());
+        };
+        let mut segments = self.segments.lock().unwrap();
+        if segments.active_len >= self.segment_bytes {
+            segments.active.flush()?;
+            let active = create_segment(&self.dir, offset)?;
+            let old_base = std::mem::replace(&mut segments.active_base, offset);
+            segments.sealed.push_back(old_base);
+            segments.active = active;
+            segments.active_len = 0;
+        }
+
+        let mut record = Vec::new();
+        for e in entries {
+            record.extend_from_slice(&encode(e.offset, e.tag, e.prev, e.payload)?);
+        }
+        if let Err(e) = segments.active.write_all(&record) {
+            // don't leave half a batch behind for the next append to follow
+            let len = segments.active_len;
+            let _ = segments.active.set_len(len);
+            let _ = segments.active.seek(SeekFrom::End(0));
+            return Err(e);
+        }
+        segments.active_len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Records that every message before `head` has been read by the quorum
+    /// and deletes the segments that only held such messages.
+    pub(crate) fn set_head(&self, head: u64) -> io::Result<()> {
+        {
+            let mut guard = self.head.lock().unwrap();
+            let (file, last) = &mut *guard;
+            // readers evicting concurrently can get here out of order
+            if head <= *last {
+                return Ok(());
+            }
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&head.to_le_bytes())?;
+            *last = head;
+        }
+
+        let mut segments = self.segments.lock().unwrap();
+        loop {
+            let next = match segments.sealed.get(1) {
+                Some(&next) => next,
+                None => segments.active_base,
+            };
+            match segments.sealed.front() {
+                Some(&base) if next <= head => {
+                    fs::remove_file(segment_path(&self.dir, base))?;
+                    segments.sealed.pop_front();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Where the topic `name` keeps its own log.
+    pub(crate) fn topic_dir(&self, name: &str) -> PathBuf {
+        self.dir.join("topics").join(encode_name(name))
+    }
+
+    /// Opens the checkpoint file for the durable reader `name`, creating it
+    /// at `offset` if it does not exist yet.
+    pub(crate) fn cursor(&self, name: &str, offset: u64) -> io::Result<CursorFile> {
+        let path = self.dir.join("cursors").join(encode_name(name));
+        let exists = path.exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut cursor = CursorFile(file);
+        if !exists {
+            cursor.store(offset)?;
+        }
+        Ok(cursor)
+    }
+
+    /// Flushes everything written so far to the disk.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        self.segments.lock().unwrap().active.sync_data()?;
+        self.head.lock().unwrap().0.sync_data()
+    }
+}
+
+// Reader names are hex encoded so any name makes a valid file name.
+fn encode_name(name: &str) -> String {
+    name.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_name(file_name: &str) -> Option<String> {
+    let bytes = (0..file_name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(file_name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn segment_path(dir: &Path, base: u64) -> PathBuf {
+    dir.join(format!("{:020}.log", base))
+}
+
+fn create_segment(dir: &Path, base: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(segment_path(dir, base))
+}
+
+// Fails for a record whose lengths don't fit in the header, rather than
+// writing one that can't be read back.
+fn encode(offset: u64, tag: &[u8], prev: &[u8], payload: &[u8]) -> io::Result<Vec<u8>> {
+    let too_long = |what| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is too long to store", what));
+    let len = u32::try_from(payload.len()).map_err(|_| too_long("message"))?;
+    let tag_len = u16::try_from(tag.len()).map_err(|_| too_long("tag"))?;
+    let prev_len = u16::try_from(prev.len()).map_err(|_| too_long("previous tag"))?;
+    let mut buf = Vec::with_capacity(RECORD_HEADER + tag.len() + prev.len() + payload.len());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(&tag_len.to_le_bytes());
+    buf.extend_from_slice(&prev_len.to_le_bytes());
+    buf.extend_from_slice(tag);
+    buf.extend_from_slice(prev);
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+// Returns the record at the start of `bytes` and its encoded length, or
+// `None` if `bytes` does not hold a complete record.
+fn decode(bytes: &[u8]) -> Option<(Record, usize)> {
+    let header = bytes.get(..RECORD_HEADER)?;
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let offset = u64::from_le_bytes(header[4..12].try_into().unwrap());
+    let tag_len = u16::from_le_bytes(header[12..14].try_into().unwrap()) as usize;
+    let prev_end = tag_len + u16::from_le_bytes(header[14..16].try_into().unwrap()) as usize;
+
+    let end = RECORD_HEADER + prev_end + len;
+    let body = bytes.get(RECORD_HEADER..end)?;
+    let record = Record {
+        offset,
+        tag: body[..tag_len].to_vec(),
+        prev: body[tag_len..prev_end].to_vec(),
+        payload: body[prev_end..].to_vec(),
+    };
+    Some((record, end))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "spmc-logger-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, SeqCst)
+            ));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn segment_count(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "log"))
+            .count()
+    }
+
+    #[test]
+    fn records_survive_reopening() {
+        let dir = TempDir::new();
+        {
+            let (storage, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+            assert_eq!((recovered.head, recovered.tail), (0, 0));
+            for x in 0..5u64 {
+                let payload = format!("{}", x);
+                let entry = Entry { offset: x, tag: &[x as u8; 4], prev: &[x as u8; 2], payload: payload.as_bytes() };
+                storage.append(&[entry]).unwrap();
+            }
+            storage.set_head(2).unwrap();
+        }
+
+        let (_, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+        assert_eq!((recovered.head, recovered.tail), (2, 5));
+        let offsets: Vec<_> = recovered.records.iter().map(|r| r.offset).collect();
+        assert_eq!(offsets, vec![2, 3, 4]);
+        assert_eq!(recovered.records[0].tag, vec![2; 4]);
+        assert_eq!(recovered.records[0].prev, vec![2; 2]);
+        assert_eq!(recovered.records[0].payload, b"2");
+        assert_eq!(recovered.last_tag, vec![4; 4]);
+    }
+
+    #[test]
+    fn cursors_survive_reopening() {
+        let dir = TempDir::new();
+        {
+            let (storage, _) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+            storage.cursor("indexer", 0).unwrap().store(3).unwrap();
+            // an existing checkpoint is not reset when the file is reopened
+            storage.cursor("indexer", 0).unwrap();
+            storage.cursor("audit/eu", 1).unwrap();
+            storage.set_head(2).unwrap();
+        }
+
+        let (_, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+        assert_eq!(
+            recovered.cursors,
+            vec![("audit/eu".to_string(), 2), ("indexer".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn torn_write_is_truncated() {
+        let dir = TempDir::new();
+        {
+            let (storage, _) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+            storage.append(&[Entry { offset: 0, tag: b"tag", prev: b"", payload: b"complete" }]).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(segment_path(&dir.0, 0)).unwrap();
+        file.write_all(&encode(1, b"tag", b"", b"torn").unwrap()[..10]).unwrap();
+
+        let (storage, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+        assert_eq!(recovered.records.len(), 1);
+        assert_eq!(recovered.tail, 1);
+        storage.append(&[Entry { offset: 1, tag: b"tag", prev: b"", payload: b"rewritten" }]).unwrap();
+        drop(storage);
+
+        let (_, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+        assert_eq!(recovered.records[1].payload, b"rewritten");
+    }
+
+    #[test]
+    fn damaged_sealed_segments_are_left_alone() {
+        let dir = TempDir::new();
+        {
+            let (storage, _) = Storage::open(&dir.0, 64).unwrap();
+            for x in 0..3u64 {
+                storage.append(&[Entry { offset: x, tag: b"tag", prev: b"", payload: &[0; 48] }]).unwrap();
+            }
+        }
+        // a bad length in the first record of a segment that is no longer
+        // written to
+        let path = segment_path(&dir.0, 0);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[0] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let err = Storage::open(&dir.0, 64).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn long_tags_round_trip() {
+        let dir = TempDir::new();
+        {
+            let (storage, _) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+            storage.append(&[Entry { offset: 0, tag: &[1; 512], prev: &[2; 300], payload: b"signed" }]).unwrap();
+            let err = storage.append(&[Entry { offset: 1, tag: &[1; 1 << 16], prev: b"", payload: b"x" }]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let (_, recovered) = Storage::open(&dir.0, SEGMENT_BYTES).unwrap();
+        assert_eq!(recovered.tail, 1);
+        assert_eq!(recovered.records[0].tag, vec![1; 512]);
+        assert_eq!(recovered.records[0].prev, vec![2; 300]);
+        assert_eq!(recovered.records[0].payload, b"signed");
+    }
+
+    #[test]
+    fn evicted_segments_are_deleted() {
+        let dir = TempDir::new();
+        let (storage, _) = Storage::open(&dir.0, 64).unwrap();
+        for x in 0..10u64 {
+            storage.append(&[Entry { offset: x, tag: b"tag", prev: b"", payload: &[0; 48] }]).unwrap();
+        }
+        assert_eq!(segment_count(&dir.0), 10);
+
+        storage.set_head(4).unwrap();
+        assert_eq!(segment_count(&dir.0), 6);
+        drop(storage);
+
+        let (_, recovered) = Storage::open(&dir.0, 64).unwrap();
+        assert_eq!(recovered.records.first().unwrap().offset, 4);
+        assert_eq!(recovered.tail, 10);
+    }
+}
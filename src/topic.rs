@@ -0,0 +1,90 @@
+use crate::{Builder, DeliveryToken, Integrity, Keys, Logger, LoggerError, Reader, Response, Signature};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// The topics created on a logger, which keeps them alive.
+pub(crate) struct Topics<I: Integrity> {
+    // how new topics are configured, apart from their size and quorum
+    pub(crate) template: Builder<()>,
+    // also held while keys are purged, see `Logger::purge_keys`
+    pub(crate) loggers: Mutex<BTreeMap<String, Logger<I>>>,
+}
+
+impl<I: Integrity> Topics<I> {
+    pub(crate) fn new(template: Builder<()>) -> Self {
+        Topics { template, loggers: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+/// A message read by a [`TopicReader`], with the topic it was read from.
+pub struct TopicResponse<'a, T = Signature> {
+    pub topic: &'a str,
+    pub response: Response<T>,
+}
+
+/// A reader subscribed to several topics at once, see
+/// [`Logger::subscribe_topics`]. It holds a [`Reader`] in each topic's
+/// quorum.
+pub struct TopicReader<I: Integrity = Keys> {
+    readers: Vec<(String, Reader<I>)>,
+    // the topic the next read looks at first, so a busy topic can't starve
+    // the others
+    next: usize,
+}
+
+impl<I: Integrity> TopicReader<I> {
+    pub(crate) fn new(readers: Vec<(String, Reader<I>)>) -> Self {
+        TopicReader { readers, next: 0 }
+    }
+
+    /// Reads the next message from any of the topics, taking them in turns.
+    /// Returns `Ok(None)` when none of them has anything new, or
+    /// [`LoggerError::Closed`] once every topic is closed and has been read.
+    pub fn read(&mut self) -> Result<Option<TopicResponse<'_, I::Tag>>, LoggerError> {
+        let len = self.readers.len();
+        let mut closed = 0;
+        for i in 0..len {
+            let id = (self.next + i) % len;
+            match self.readers[id].1.read() {
+                Ok(Some(response)) => {
+                    self.next = id + 1;
+                    return Ok(Some(TopicResponse { topic: &self.readers[id].0, response }));
+                }
+                Ok(None) => (),
+                Err(LoggerError::Closed) => closed += 1,
+                Err(err) => {
+                    self.next = id + 1;
+                    return Err(err);
+                }
+            }
+        }
+        if closed == len {
+            Err(LoggerError::Closed)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Acknowledges a message read from `topic`, see [`Reader::ack`].
+    pub fn ack(&self, topic: &str, token: DeliveryToken) -> Result<(), LoggerError> {
+        self.reader(topic)?.ack(token)
+    }
+
+    /// Gives back a message read from `topic`, see [`Reader::nack`].
+    pub fn nack(&self, topic: &str, token: DeliveryToken) -> Result<(), LoggerError> {
+        self.reader(topic)?.nack(token)
+    }
+
+    fn reader(&self, topic: &str) -> Result<&Reader<I>, LoggerError> {
+        self.readers
+            .iter()
+            .find(|(name, _)| name == topic)
+            .map(|(_, reader)| reader)
+            .ok_or_else(|| LoggerError::UnknownTopic { name: topic.to_string() })
+    }
+
+    /// The topics this reader is subscribed to.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.readers.iter().map(|(name, _)| name.as_str())
+    }
+}
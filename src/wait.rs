@@ -0,0 +1,90 @@
+use std::hint;
+use std::sync::atomic::{fence, AtomicUsize, Ordering::SeqCst};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// How a blocked reader waits for the writer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+    /// Busy-wait on the CPU. Lowest latency, but burns a core while waiting.
+    Spin,
+    /// Give the rest of the time slice to another thread between checks.
+    Yield,
+    /// Sleep until the writer wakes the reader up. Uses no CPU while waiting
+    /// at the cost of a wake-up on every write that has someone waiting.
+    #[default]
+    Park,
+}
+
+/// Lets threads sleep until some condition changes, for the `Park` strategy.
+/// Whoever changes the condition calls `notify`, which is a single atomic
+/// load when nobody is parked.
+pub(crate) struct Notify {
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+impl Notify {
+    pub(crate) fn new() -> Self {
+        Notify {
+            waiters: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Wakes everyone parked in `wait`. Call it after the change that makes
+    /// their condition true has been stored.
+    pub(crate) fn notify(&self) {
+        // order the caller's store before the load of `waiters`, pairing with
+        // the increment in `wait`
+        fence(SeqCst);
+        if self.waiters.load(SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.cond.notify_all();
+        }
+    }
+
+    /// Waits until `ready` returns true, or returns false once `deadline`
+    /// has passed.
+    pub(crate) fn wait(
+        &self,
+        strategy: WaitStrategy,
+        deadline: Option<Instant>,
+        ready: impl Fn() -> bool,
+    ) -> bool {
+        loop {
+            if ready() {
+                return true;
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return false;
+            }
+
+            match strategy {
+                WaitStrategy::Spin => hint::spin_loop(),
+                WaitStrategy::Yield => thread::yield_now(),
+                WaitStrategy::Park => {
+                    self.waiters.fetch_add(1, SeqCst);
+                    let guard = self.lock.lock().unwrap();
+                    // `notify` takes the lock, so it either happens before
+                    // this check or wakes us up from the wait below
+                    if !ready() {
+                        match deadline {
+                            Some(d) => {
+                                let timeout = d.saturating_duration_since(Instant::now());
+                                drop(self.cond.wait_timeout(guard, timeout).unwrap());
+                            }
+                            None => {
+                                drop(self.cond.wait(guard).unwrap());
+                            }
+                        }
+                    }
+                    self.waiters.fetch_sub(1, SeqCst);
+                }
+            }
+        }
+    }
+}